@@ -1,14 +1,39 @@
+use std::collections::HashMap;
+
 use pgrx::prelude::*;
 use serde_json::json;
 
-use crate::guc::{base_url, AUTO_ACCEPT_FOLLOWS};
-use crate::util::json_str;
+use crate::crypto::open_private_key;
+use crate::delivery::{
+    queue_actor_deliveries, queue_follower_deliveries, queue_same_inboxes, record_signature_scheme,
+};
+use crate::guc::{
+    base_url, AUTO_ACCEPT_FOLLOWS, SIGNATURE_MAX_SKEW_SECONDS, SIGN_ACTIVITIES, USE_HTTPS,
+};
+use crate::integrity::{add_integrity_context, attach_proof, verify_document_proof};
+use crate::media::sync_attachments;
+use crate::notifications::{notify, notify_audience, notify_author, retract};
+use crate::pins::{link_pins, process_featured_change};
+use crate::polls::{process_vote, record_poll, sync_remote_poll, NewPoll};
+use crate::tags::{
+    record_mentions, resolve_mentions, sync_hashtags, sync_mentions, sync_object_tags,
+};
+use crate::types::{ApNotificationType, ApSignatureScheme, ApVisibility};
+use crate::util::{
+    json_id, json_str, normalize_headers, parse_hashtags, parse_http_date, unix_now, AS_PUBLIC,
+};
 
 // =============================================================================
 // Note creation (outbox)
 // =============================================================================
 
-/// Create a Note and wrap it in a Create activity, queuing delivery to followers.
+/// Create a Note and wrap it in a Create activity, queuing delivery to its
+/// audience. `visibility` drives the to/cc addressing; `recipients` are actor
+/// URIs addressed explicitly (required for Direct, added to cc otherwise).
+/// `@user@domain` mentions of known actors are added to the recipients, and
+/// mentions and `#hashtags` are recorded as the object's tags. `attachments`
+/// is an ActivityStreams `attachment` array (url, mediaType, name, width,
+/// height, blurhash, focalPoint).
 /// Returns the Note's URI.
 #[pg_extern]
 fn ap_create_note(
@@ -16,7 +41,46 @@ fn ap_create_note(
     content: &str,
     summary: Option<&str>,
     in_reply_to: Option<&str>,
+    visibility: default!(ApVisibility, "'Public'"),
+    recipients: default!(Option<Vec<String>>, "NULL"),
+    attachments: default!(Option<pgrx::Json>, "NULL"),
 ) -> String {
+    publish_post(&LocalPost {
+        username,
+        content,
+        summary,
+        in_reply_to,
+        visibility,
+        recipients: recipients.unwrap_or_default(),
+        attachments: attachments.as_ref().map(|a| &a.0),
+        poll: None,
+    })
+}
+
+/// A local post to be published by `publish_post`.
+pub struct LocalPost<'a> {
+    pub username: &'a str,
+    pub content: &'a str,
+    pub summary: Option<&'a str>,
+    pub in_reply_to: Option<&'a str>,
+    pub visibility: ApVisibility,
+    pub recipients: Vec<String>,
+    pub attachments: Option<&'a serde_json::Value>,
+    /// Publishes a Question instead of a Note
+    pub poll: Option<NewPoll>,
+}
+
+/// Store a local post and wrap it in a Create activity queued for its
+/// audience (see `ap_create_note`). Returns the object's URI.
+pub fn publish_post(post: &LocalPost) -> String {
+    let LocalPost {
+        username,
+        content,
+        summary,
+        in_reply_to,
+        visibility,
+        ..
+    } = *post;
     let base = base_url();
 
     // Look up the local actor
@@ -28,6 +92,25 @@ fn ap_create_note(
     .expect("local actor not found");
 
     let actor_uri = format!("{}/users/{}", base, username);
+    let followers_uri = format!("{}/followers", actor_uri);
+
+    // Strip HTML to plain text for full-text search and tag extraction
+    let content_text = strip_html(content);
+    let mentions = resolve_mentions(&content_text);
+    let hashtags = parse_hashtags(&content_text);
+
+    let mut recipients = post.recipients.clone();
+    for (mentioned_uri, _) in &mentions {
+        if !recipients.contains(mentioned_uri) {
+            recipients.push(mentioned_uri.clone());
+        }
+    }
+    if visibility == ApVisibility::Direct && recipients.is_empty() {
+        pgrx::error!("Direct visibility requires at least one recipient");
+    }
+    ensure_actors_known(&recipients);
+
+    let (to_uris, cc_uris) = build_addressing(visibility, &followers_uri, &recipients);
 
     // Generate a unique object URI using the DB sequence
     let object_id = Spi::get_one::<i64>("SELECT nextval('ap_objects_id_seq')")
@@ -51,14 +134,18 @@ fn ap_create_note(
         None => format!("{}/conversations/{}", base, object_id),
     };
 
-    // Strip HTML to plain text for full-text search
-    let content_text = strip_html(content);
+    let object_type = if post.poll.is_some() {
+        "Question"
+    } else {
+        "Note"
+    };
 
     // Insert the object (using the pre-allocated ID)
     Spi::run_with_args(
         "INSERT INTO ap_objects (id, uri, object_type, actor_id, content, content_text,
-            summary, url, visibility, in_reply_to_uri, conversation_uri, published_at)
-         VALUES ($1, $2, 'Note', $3, $4, $5, $6, $7, 'Public', $8, $9, now())",
+            summary, url, visibility, to_uris, cc_uris, in_reply_to_uri, conversation_uri,
+            published_at)
+         VALUES ($1, $2, $13::ApObjectType, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, now())",
         &[
             object_id.into(),
             object_uri.clone().into(),
@@ -67,49 +154,451 @@ fn ap_create_note(
             content_text.into(),
             summary.into(),
             object_url.into(),
+            visibility.into(),
+            to_uris.clone().into(),
+            cc_uris.clone().into(),
             in_reply_to.into(),
             conversation_uri.into(),
+            object_type.into(),
         ],
     )
     .expect("failed to insert object");
 
-    // Build the Create activity
+    record_recipients(object_id, &recipients);
+    record_mentions(object_id, &mentions);
+    sync_hashtags(object_id, &hashtags);
+    sync_attachments(object_id, post.attachments);
+    if let Some(poll) = &post.poll {
+        record_poll(object_id, poll);
+    }
+    notify_audience(object_id, actor_id);
+
+    // Build the Create activity around the serialized object
+    let (activity_id, activity_uri) = allocate_activity(&actor_uri);
+    let mut note = crate::serialization::serialize_object(&object_uri).0;
+    note.as_object_mut().unwrap().remove("@context");
+
+    let create_json = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": activity_uri,
+        "type": "Create",
+        "actor": actor_uri,
+        "published": note["published"],
+        "to": to_uris,
+        "cc": cc_uris,
+        "object": note
+    });
+
+    store_local_activity(activity_id, actor_id, &create_json);
+
+    // Queue delivery to followers (unless Direct) and explicit recipients
+    if visibility != ApVisibility::Direct {
+        queue_follower_deliveries(activity_id, actor_id);
+    }
+    queue_actor_deliveries(activity_id, &recipients);
+
+    object_uri
+}
+
+/// Edit a local note: replace its content, content warning, mentions and
+/// hashtags (and attachments, unless `attachments` is NULL), set `edited_at`,
+/// and federate an Update carrying the full new object to the same inboxes
+/// the original Create was delivered to. Actors newly mentioned are added to
+/// the addressing and receive the Update too; nobody already addressed is
+/// dropped.
+/// Returns the Update activity's URI.
+#[pg_extern]
+fn ap_update_note(
+    username: &str,
+    object_uri: &str,
+    content: &str,
+    summary: Option<&str>,
+    attachments: default!(Option<pgrx::Json>, "NULL"),
+) -> String {
+    let (actor_id, actor_uri) = owned_local_object(username, object_uri);
+    let followers_uri = format!("{}/followers", actor_uri);
+    let content_text = strip_html(content);
+    let mentions = resolve_mentions(&content_text);
+
+    let (visibility, recorded) = Spi::get_two_with_args::<ApVisibility, Vec<String>>(
+        "SELECT o.visibility, coalesce((
+            SELECT array_agg(r.actor_uri ORDER BY r.actor_uri)
+            FROM ap_object_recipients r WHERE r.object_id = o.id
+         ), '{}')
+         FROM ap_objects o WHERE o.uri = $1",
+        &[object_uri.into()],
+    )
+    .expect("failed to query object addressing");
+    let visibility = visibility.expect("object not found");
+
+    let mut recipients = recorded.unwrap_or_default();
+    for (mentioned_uri, _) in &mentions {
+        if !recipients.contains(mentioned_uri) {
+            recipients.push(mentioned_uri.clone());
+        }
+    }
+    let (to_uris, cc_uris) = build_addressing(visibility, &followers_uri, &recipients);
+
+    let object_id = Spi::get_one_with_args::<i64>(
+        "UPDATE ap_objects SET content = $2, content_text = $3, summary = $4,
+            to_uris = $5, cc_uris = $6, edited_at = now()
+         WHERE uri = $1
+         RETURNING id",
+        &[
+            object_uri.into(),
+            content.into(),
+            content_text.clone().into(),
+            summary.into(),
+            to_uris.into(),
+            cc_uris.into(),
+        ],
+    )
+    .expect("failed to update object")
+    .expect("object not found");
+
+    record_recipients(object_id, &recipients);
+    sync_mentions(object_id, &mentions);
+    sync_hashtags(object_id, &parse_hashtags(&content_text));
+    if let Some(attachments) = attachments {
+        sync_attachments(object_id, Some(&attachments.0));
+    }
+
+    let mut note = crate::serialization::serialize_object(object_uri).0;
+    note.as_object_mut().unwrap().remove("@context");
+
+    let (activity_id, activity_uri) = allocate_activity(&actor_uri);
+    let update_json = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": activity_uri,
+        "type": "Update",
+        "actor": actor_uri,
+        "published": note["updated"],
+        "to": note["to"],
+        "cc": note["cc"],
+        "object": note
+    });
+
+    store_local_activity(activity_id, actor_id, &update_json);
+    queue_create_audience(activity_id, object_uri);
+    queue_actor_deliveries(activity_id, &recipients);
+    notify_audience(object_id, actor_id);
+
+    activity_uri
+}
+
+/// Delete a local note: soft-delete the row, set `deleted_at`, unpin it and
+/// drop its hashtags and notifications, and federate a Delete with a
+/// Tombstone to the same inboxes the original Create reached.
+/// Returns the Delete activity's URI.
+#[pg_extern]
+fn ap_delete_note(username: &str, object_uri: &str) -> String {
+    let (actor_id, actor_uri) = owned_local_object(username, object_uri);
+
+    let (object_id, to_uris, cc_uris) = Spi::get_three_with_args::<i64, Vec<String>, Vec<String>>(
+        "UPDATE ap_objects SET deleted_at = now(), content = NULL, content_text = NULL
+             WHERE uri = $1
+             RETURNING id, to_uris, cc_uris",
+        &[object_uri.into()],
+    )
+    .expect("failed to delete object");
+    forget_deleted_object(object_id.expect("object not found"));
+
+    let (activity_id, activity_uri) = allocate_activity(&actor_uri);
+    let delete_json = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": activity_uri,
+        "type": "Delete",
+        "actor": actor_uri,
+        "to": to_uris.unwrap_or_else(|| vec![AS_PUBLIC.to_string()]),
+        "cc": cc_uris.unwrap_or_default(),
+        "object": {
+            "id": object_uri,
+            "type": "Tombstone"
+        }
+    });
+
+    store_local_activity(activity_id, actor_id, &delete_json);
+    queue_create_audience(activity_id, object_uri);
+
+    activity_uri
+}
+
+/// Drop what still points at a tombstoned object: its pins, hashtags (so it
+/// leaves tag timelines and trends) and notifications.
+fn forget_deleted_object(object_id: i64) {
+    Spi::run_with_args(
+        "DELETE FROM ap_pins WHERE object_id = $1",
+        &[object_id.into()],
+    )
+    .expect("failed to remove pins of deleted object");
+    Spi::run_with_args(
+        "DELETE FROM ap_object_hashtags WHERE object_id = $1",
+        &[object_id.into()],
+    )
+    .expect("failed to remove hashtags of deleted object");
+    Spi::run_with_args(
+        "DELETE FROM ap_notifications WHERE object_id = $1",
+        &[object_id.into()],
+    )
+    .expect("failed to remove notifications of deleted object");
+}
+
+/// Resolve a live object owned by a local actor, returning the actor's id and
+/// URI. Errors if the object does not exist, is deleted, or belongs to someone else.
+fn owned_local_object(username: &str, object_uri: &str) -> (i64, String) {
+    let owner = Spi::get_two_with_args::<i64, String>(
+        "SELECT a.id, a.uri FROM ap_objects o
+         JOIN ap_actors a ON a.id = o.actor_id
+         WHERE o.uri = $1 AND a.username = $2 AND a.domain IS NULL
+         AND o.deleted_at IS NULL",
+        &[object_uri.into(), username.into()],
+    );
+
+    match owner {
+        Ok((Some(id), Some(uri))) => (id, uri),
+        _ => pgrx::error!(
+            "note '{}' not found for local actor '{}'",
+            object_uri,
+            username
+        ),
+    }
+}
+
+/// Queue an activity about an object to every inbox its Create was delivered to.
+fn queue_create_audience(activity_id: i64, object_uri: &str) {
+    let create_id = Spi::get_one_with_args::<i64>(
+        "SELECT id FROM ap_activities
+         WHERE object_uri = $1 AND activity_type = 'Create' AND local = true",
+        &[object_uri.into()],
+    )
+    .ok()
+    .flatten();
+
+    if let Some(create_id) = create_id {
+        queue_same_inboxes(activity_id, create_id);
+    }
+}
+
+/// Build the to/cc addressing for a post of the given visibility, following
+/// the Mastodon conventions remote servers use to infer visibility back.
+pub fn build_addressing(
+    visibility: ApVisibility,
+    followers_uri: &str,
+    recipients: &[String],
+) -> (Vec<String>, Vec<String>) {
+    let mut to = Vec::new();
+    let mut cc = Vec::new();
+
+    match visibility {
+        ApVisibility::Public => {
+            to.push(AS_PUBLIC.to_string());
+            cc.push(followers_uri.to_string());
+            cc.extend(recipients.iter().cloned());
+        }
+        ApVisibility::Unlisted => {
+            to.push(followers_uri.to_string());
+            cc.push(AS_PUBLIC.to_string());
+            cc.extend(recipients.iter().cloned());
+        }
+        ApVisibility::FollowersOnly => {
+            to.push(followers_uri.to_string());
+            cc.extend(recipients.iter().cloned());
+        }
+        ApVisibility::Direct => {
+            to.extend(recipients.iter().cloned());
+        }
+    }
+
+    (to, cc)
+}
+
+/// Infer the visibility of an object from its addressing. This is the inverse
+/// of `build_addressing`: Public in `to` is public, Public in `cc` is
+/// unlisted, a followers collection without Public is followers-only, and
+/// anything else is a direct message.
+pub fn classify_visibility(
+    to: &[String],
+    cc: &[String],
+    followers_uri: Option<&str>,
+) -> ApVisibility {
+    if to.iter().any(|u| is_public_uri(u)) {
+        ApVisibility::Public
+    } else if cc.iter().any(|u| is_public_uri(u)) {
+        ApVisibility::Unlisted
+    } else if to
+        .iter()
+        .chain(cc)
+        .any(|u| is_collection_uri(u, followers_uri))
+    {
+        ApVisibility::FollowersOnly
+    } else {
+        ApVisibility::Direct
+    }
+}
+
+/// The actor URIs an object is explicitly addressed to, i.e. its to/cc
+/// without the Public pseudo-collection and followers/following collections.
+pub fn explicit_recipients(
+    to: &[String],
+    cc: &[String],
+    followers_uri: Option<&str>,
+) -> Vec<String> {
+    let mut recipients: Vec<String> = Vec::new();
+    for uri in to.iter().chain(cc) {
+        if is_public_uri(uri) || is_collection_uri(uri, followers_uri) {
+            continue;
+        }
+        if !recipients.contains(uri) {
+            recipients.push(uri.clone());
+        }
+    }
+    recipients
+}
+
+/// Record the explicit recipients of an object, linking those we know as actors.
+pub fn record_recipients(object_id: i64, actor_uris: &[String]) {
+    if actor_uris.is_empty() {
+        return;
+    }
+
+    Spi::run_with_args(
+        "INSERT INTO ap_object_recipients (object_id, actor_uri, actor_id)
+         SELECT $1, u, (SELECT id FROM ap_actors WHERE uri = u)
+         FROM unnest($2::text[]) u
+         ON CONFLICT (object_id, actor_uri) DO NOTHING",
+        &[object_id.into(), actor_uris.to_vec().into()],
+    )
+    .expect("failed to record object recipients");
+}
+
+/// Public may be addressed by its full IRI or by its compacted forms.
+fn is_public_uri(uri: &str) -> bool {
+    uri == AS_PUBLIC || uri == "as:Public" || uri == "Public"
+}
+
+/// Whether a URI is a followers/following collection rather than an actor.
+/// Stub actors have no `followers_uri` yet, so fall back to the path suffix.
+fn is_collection_uri(uri: &str, followers_uri: Option<&str>) -> bool {
+    followers_uri == Some(uri) || uri.ends_with("/followers") || uri.ends_with("/following")
+}
+
+/// Reject addressing to actors we have never seen, since we could neither
+/// deliver to them nor resolve them later.
+fn ensure_actors_known(actor_uris: &[String]) {
+    if actor_uris.is_empty() {
+        return;
+    }
+
+    let unknown = Spi::get_one_with_args::<String>(
+        "SELECT string_agg(u, ', ') FROM unnest($1::text[]) u
+         WHERE NOT EXISTS (SELECT 1 FROM ap_actors WHERE uri = u)",
+        &[actor_uris.to_vec().into()],
+    )
+    .expect("failed to check recipients");
+
+    if let Some(unknown) = unknown {
+        pgrx::error!("unknown recipient actor(s): {}", unknown);
+    }
+}
+
+/// Allocate an activity id from the sequence and mint its URI under the actor.
+pub fn allocate_activity(actor_uri: &str) -> (i64, String) {
     let activity_id = Spi::get_one::<i64>("SELECT nextval('ap_activities_id_seq')")
         .unwrap()
         .unwrap();
-    let activity_uri = format!("{}/activities/{}", actor_uri, activity_id);
+    (
+        activity_id,
+        format!("{}/activities/{}", actor_uri, activity_id),
+    )
+}
 
-    let public = "https://www.w3.org/ns/activitystreams#Public";
-    let followers_uri = format!("{}/followers", actor_uri);
+/// Store a locally-originated activity, deriving the indexed columns
+/// (type, object, target, addressing) from the JSON document itself. With
+/// `pg_fedi.sign_activities` on, the stored (and delivered) document carries
+/// an integrity proof by the actor's current Ed25519 key, if it has one.
+pub fn store_local_activity(activity_id: i64, actor_id: i64, activity: &serde_json::Value) {
+    let activity_type = json_str(activity, "type").expect("activity missing 'type'");
+    let activity = &sign_local_activity(actor_id, activity);
 
     Spi::run_with_args(
-        "INSERT INTO ap_activities (id, uri, activity_type, actor_id, object_uri,
-            to_uris, cc_uris, local, processed)
-         VALUES ($1, $2, 'Create', $3, $4, $5, $6, true, true)",
+        "INSERT INTO ap_activities (id, uri, activity_type, actor_id, object_uri, target_uri,
+            to_uris, cc_uris, raw, local, processed)
+         VALUES ($1, $2, $3::ApActivityType, $4, $5, $6, $7, $8, $9, true, true)",
         &[
             activity_id.into(),
-            activity_uri.into(),
+            json_str(activity, "id").into(),
+            activity_type.into(),
             actor_id.into(),
-            object_uri.clone().into(),
-            vec![public.to_string()].into(),
-            vec![followers_uri.clone()].into(),
+            json_id(activity, "object").into(),
+            json_id(activity, "target").into(),
+            json_str_array(activity, "to").into(),
+            json_str_array(activity, "cc").into(),
+            pgrx::JsonB(activity.clone()).into(),
         ],
     )
     .expect("failed to insert activity");
+}
 
-    // Queue delivery to all followers
-    Spi::run_with_args(
-        "INSERT INTO ap_deliveries (activity_id, inbox_uri)
-         SELECT $1, COALESCE(a.shared_inbox_uri, a.inbox_uri)
-         FROM ap_follows f
-         JOIN ap_actors a ON a.id = f.follower_id
-         WHERE f.following_id = $2 AND f.accepted = true
-         AND a.domain IS NOT NULL",
-        &[activity_id.into(), actor_id.into()],
-    )
-    .expect("failed to queue deliveries");
+/// Attach an eddsa-jcs-2022 proof to a local activity when
+/// `pg_fedi.sign_activities` is on and the actor has a current Ed25519 key.
+fn sign_local_activity(actor_id: i64, activity: &serde_json::Value) -> serde_json::Value {
+    if !SIGN_ACTIVITIES.get() {
+        return activity.clone();
+    }
 
-    object_uri
+    let key = Spi::get_two_with_args::<String, String>(
+        "SELECT key_id, private_key_pem FROM ap_keys
+         WHERE actor_id = $1 AND key_type = 'Ed25519' AND expires_at IS NULL
+         AND private_key_pem IS NOT NULL
+         ORDER BY id DESC LIMIT 1",
+        &[actor_id.into()],
+    );
+    match key {
+        Ok((Some(key_id), Some(private_key_pem))) => {
+            let mut signed = activity.clone();
+            add_integrity_context(&mut signed);
+            attach_proof(&signed, &key_id, &open_private_key(&private_key_pem))
+        }
+        _ => activity.clone(),
+    }
+}
+
+/// Undo a locally-originated activity by federating an Undo that embeds the
+/// original, queued to every inbox the original was queued for.
+/// Returns the Undo's URI.
+pub fn undo_local_activity(actor_id: i64, actor_uri: &str, original_uri: &str) -> String {
+    let original = Spi::get_two_with_args::<i64, pgrx::JsonB>(
+        "SELECT id, raw FROM ap_activities WHERE uri = $1 AND actor_id = $2 AND local = true",
+        &[original_uri.into(), actor_id.into()],
+    );
+    let (original_id, mut original) = match original {
+        Ok((Some(id), Some(raw))) => (id, raw.0),
+        _ => pgrx::error!("local activity '{}' not found", original_uri),
+    };
+    if let Some(obj) = original.as_object_mut() {
+        obj.remove("@context");
+        obj.remove("proof");
+    }
+
+    let (activity_id, activity_uri) = allocate_activity(actor_uri);
+    let mut undo_json = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": activity_uri,
+        "type": "Undo",
+        "actor": actor_uri,
+    });
+    // Address the Undo like the original
+    for key in ["to", "cc"] {
+        if let Some(addressing) = original.get(key) {
+            undo_json[key] = addressing.clone();
+        }
+    }
+    undo_json["object"] = original;
+
+    store_local_activity(activity_id, actor_id, &undo_json);
+    queue_same_inboxes(activity_id, original_id);
+
+    activity_uri
 }
 
 // =============================================================================
@@ -117,12 +606,236 @@ fn ap_create_note(
 // =============================================================================
 
 /// Main inbox entry point. Receives raw ActivityStreams JSON, classifies it,
-/// and dispatches to the appropriate handler.
+/// and dispatches to the appropriate handler. The caller is responsible for
+/// having verified the request's HTTP Signature; see
+/// `ap_process_signed_inbox_activity` to have the extension do it.
+///
+/// An activity carrying an integrity proof (FEP-8b32) must have a proof that
+/// verifies against one of the actor's known Ed25519 keys; otherwise an
+/// `invalid_authorization_specification` (28000) error is raised.
 /// Returns the activity URI on success.
 #[pg_extern]
 fn ap_process_inbox_activity(body: pgrx::Json) -> String {
-    let obj = &body.0;
+    let actor_uri = json_str(&body.0, "actor").expect("activity missing 'actor'");
+    check_integrity_proof(&body.0, &actor_uri);
+    process_activity(&body.0)
+}
 
+/// Signature-verifying inbox entry point. Takes the request as received:
+/// method, path, a JSON object of request headers, and the raw body.
+///
+/// Requests carrying a `Signature-Input` header are verified as RFC 9421
+/// message signatures, which must cover `@method`, the target URI (or
+/// `@authority` and `@path`) and `content-digest`, with a `created` time.
+/// Other requests are verified as draft-cavage signatures over
+/// (request-target), host, date and digest.
+///
+/// Either way, the key id is resolved against `ap_keys`, the body digest is
+/// checked, the signing time is checked against
+/// `pg_fedi.signature_max_skew_seconds`, and the key's owner must be the
+/// activity's `actor` — unless the activity carries a valid integrity proof
+/// by its actor, in which case any known server may relay or forward it.
+/// Any failure raises an `invalid_authorization_specification` (28000) error
+/// and nothing is stored.
+#[pg_extern]
+fn ap_process_signed_inbox_activity(
+    method: &str,
+    path: &str,
+    headers: pgrx::Json,
+    body: &str,
+) -> String {
+    let headers = normalize_headers(&headers.0);
+
+    let obj: serde_json::Value = serde_json::from_str(body)
+        .unwrap_or_else(|e| pgrx::error!("activity body is not valid JSON: {}", e));
+    let actor_uri = json_str(&obj, "actor").expect("activity missing 'actor'");
+
+    let owner_uri = if headers.contains_key("signature-input") {
+        verify_rfc9421_request(method, path, &headers, body)
+    } else {
+        verify_cavage_request(method, path, &headers, body)
+    };
+
+    let proven = check_integrity_proof(&obj, &actor_uri);
+    if owner_uri != actor_uri && !proven {
+        reject_signature("key owner does not match activity actor");
+    }
+
+    process_activity(&obj)
+}
+
+/// Verify a draft-cavage signed request, returning the signing key's owner.
+fn verify_cavage_request(
+    method: &str,
+    path: &str,
+    headers: &HashMap<String, String>,
+    body: &str,
+) -> String {
+    let signature = headers
+        .get("signature")
+        .unwrap_or_else(|| reject_signature("missing Signature header"));
+    let fields = crate::crypto::parse_signature_header(signature)
+        .unwrap_or_else(|| reject_signature("malformed Signature header"));
+    let key_id = fields
+        .key_id
+        .as_deref()
+        .unwrap_or_else(|| reject_signature("Signature header has no keyId"));
+
+    // The signature must cover the request line, host, date and body digest
+    let signed: Vec<&str> = fields
+        .headers
+        .as_deref()
+        .unwrap_or("date")
+        .split_whitespace()
+        .collect();
+    for required in ["(request-target)", "host", "date", "digest"] {
+        if !signed.contains(&required) {
+            reject_signature(&format!("signature does not cover '{}'", required));
+        }
+    }
+
+    let host = headers
+        .get("host")
+        .unwrap_or_else(|| reject_signature("missing Host header"));
+    let date = headers
+        .get("date")
+        .unwrap_or_else(|| reject_signature("missing Date header"));
+    let digest = headers
+        .get("digest")
+        .unwrap_or_else(|| reject_signature("missing Digest header"));
+
+    if !crate::crypto::digest_matches(digest, body) {
+        reject_signature("Digest does not match body");
+    }
+
+    let sent_at =
+        parse_http_date(date).unwrap_or_else(|| reject_signature("unparseable Date header"));
+    if !within_signature_skew(sent_at) {
+        reject_signature("Date is outside the allowed clock skew");
+    }
+
+    let (public_key_pem, owner_uri) = signing_key(key_id);
+
+    if !crate::crypto::ap_verify_http_signature(
+        signature,
+        method,
+        path,
+        host,
+        date,
+        Some(digest.as_str()),
+        &public_key_pem,
+    ) {
+        reject_signature("signature verification failed");
+    }
+
+    owner_uri
+}
+
+/// Verify an RFC 9421 signed request, returning the signing key's owner.
+/// A valid signature also records that the owner's server speaks RFC 9421.
+fn verify_rfc9421_request(
+    method: &str,
+    path: &str,
+    headers: &HashMap<String, String>,
+    body: &str,
+) -> String {
+    let signature_input = &headers["signature-input"];
+    let signature = headers
+        .get("signature")
+        .unwrap_or_else(|| reject_signature("missing Signature header"));
+    let (input, sig_b64) = crate::crypto::select_message_signature(signature_input, signature)
+        .unwrap_or_else(|| reject_signature("malformed Signature-Input or Signature header"));
+    let key_id = input
+        .key_id
+        .as_deref()
+        .unwrap_or_else(|| reject_signature("Signature-Input has no keyid"));
+
+    // The signature must cover the method, target and body digest
+    let covers_target =
+        input.covers("@target-uri") || (input.covers("@authority") && input.covers("@path"));
+    if !input.covers("@method") || !covers_target || !input.covers("content-digest") {
+        reject_signature("signature does not cover @method, the target URI and content-digest");
+    }
+
+    let host = headers
+        .get("host")
+        .unwrap_or_else(|| reject_signature("missing Host header"));
+    let content_digest = headers
+        .get("content-digest")
+        .unwrap_or_else(|| reject_signature("missing Content-Digest header"));
+
+    if !crate::crypto::content_digest_matches(content_digest, body) {
+        reject_signature("Content-Digest does not match body");
+    }
+
+    let created = input
+        .created
+        .unwrap_or_else(|| reject_signature("Signature-Input has no created time"));
+    if !within_signature_skew(created) {
+        reject_signature("created time is outside the allowed clock skew");
+    }
+    if input.expires.is_some_and(|expires| expires < unix_now()) {
+        reject_signature("signature has expired");
+    }
+
+    let (public_key_pem, owner_uri) = signing_key(key_id);
+
+    let scheme = if USE_HTTPS.get() { "https" } else { "http" };
+    let target_uri = format!("{}://{}{}", scheme, host, path);
+    if !crate::crypto::verify_message_signature(
+        &input,
+        &sig_b64,
+        method,
+        &target_uri,
+        headers,
+        &public_key_pem,
+    ) {
+        reject_signature("signature verification failed");
+    }
+
+    if let Some(domain) = crate::util::parse_domain(&owner_uri) {
+        record_signature_scheme(&domain, ApSignatureScheme::Rfc9421);
+    }
+
+    owner_uri
+}
+
+/// Verify an inbound activity's integrity proof, if it carries one. Returns
+/// true if a proof by the activity's actor verifies, false if there is no
+/// proof; a proof that fails or was made by anyone else is rejected.
+fn check_integrity_proof(obj: &serde_json::Value, actor_uri: &str) -> bool {
+    match verify_document_proof(obj) {
+        Ok(None) => false,
+        Ok(Some(owner_uri)) if owner_uri == actor_uri => true,
+        Ok(Some(_)) => reject_proof("proof was not made by the activity actor"),
+        Err(reason) => reject_proof(&reason),
+    }
+}
+
+/// Whether a signing time is close enough to now, per
+/// `pg_fedi.signature_max_skew_seconds`.
+fn within_signature_skew(signed_at: i64) -> bool {
+    let max_skew = i64::from(SIGNATURE_MAX_SKEW_SECONDS.get());
+    (unix_now() - signed_at).abs() <= max_skew
+}
+
+/// Look up a signing key by key id, returning (public_key_pem, owner_uri).
+/// Keys rotated out are honoured until their grace period ends.
+fn signing_key(key_id: &str) -> (String, String) {
+    let key = Spi::get_two_with_args::<String, String>(
+        "SELECT k.public_key_pem, a.uri FROM ap_keys k
+         JOIN ap_actors a ON a.id = k.actor_id
+         WHERE k.key_id = $1 AND (k.expires_at IS NULL OR k.expires_at > now())",
+        &[key_id.into()],
+    );
+    match key {
+        Ok((Some(pem), Some(owner))) => (pem, owner),
+        _ => reject_signature(&format!("unknown signing key '{}'", key_id)),
+    }
+}
+
+/// Classify, store and dispatch an inbound activity to its handler.
+fn process_activity(obj: &serde_json::Value) -> String {
     let activity_type = json_str(obj, "type").expect("activity missing 'type'");
     let activity_uri = json_str(obj, "id");
     let actor_uri = json_str(obj, "actor").expect("activity missing 'actor'");
@@ -171,6 +884,11 @@ fn ap_process_inbox_activity(body: pgrx::Json) -> String {
 
     let target_uri = json_str(obj, "target");
 
+    // Drop activities aimed at a local actor who has blocked the sender
+    if is_blocked_by_recipient(actor_id, obj, object_uri.as_deref()) {
+        return String::new();
+    }
+
     // Store the activity
     let stored_id = Spi::get_one_with_args::<i64>(
         "INSERT INTO ap_activities (uri, activity_type, actor_id, object_uri, target_uri,
@@ -204,6 +922,8 @@ fn ap_process_inbox_activity(body: pgrx::Json) -> String {
         "Accept" => process_accept(actor_id, obj),
         "Reject" => process_reject(actor_id, obj),
         "Block" => process_block(stored_id, actor_id, &object_uri),
+        "Add" => process_featured_change(actor_id, true, obj),
+        "Remove" => process_featured_change(actor_id, false, obj),
         _ => {
             pgrx::warning!("unhandled activity type: {}", activity_type);
         }
@@ -245,83 +965,97 @@ fn process_follow(_activity_id: i64, follower_actor_id: i64, activity: &serde_js
     .expect("failed to query target actor")
     .expect("Follow target actor not found");
 
-    // Insert or update the follow
-    Spi::run_with_args(
+    // Actors that manually approve followers keep the request pending until
+    // ap_accept_follow_request; everyone else follows the global GUC
+    let (target_is_local, target_approves) = Spi::get_two_with_args::<bool, bool>(
+        "SELECT domain IS NULL, manually_approves_followers FROM ap_actors WHERE id = $1",
+        &[following_id.into()],
+    )
+    .expect("failed to query target actor");
+    let auto_accept = AUTO_ACCEPT_FOLLOWS.get() && !target_approves.unwrap_or(false);
+
+    // Insert or update the follow. A repeated Follow never revokes an
+    // existing acceptance.
+    let accepted = Spi::get_one_with_args::<bool>(
         "INSERT INTO ap_follows (follower_id, following_id, uri, accepted)
          VALUES ($1, $2, $3, $4)
          ON CONFLICT (follower_id, following_id) DO UPDATE SET
-            accepted = EXCLUDED.accepted,
-            uri = COALESCE(EXCLUDED.uri, ap_follows.uri)",
+            accepted = ap_follows.accepted OR EXCLUDED.accepted,
+            uri = COALESCE(EXCLUDED.uri, ap_follows.uri)
+         RETURNING accepted",
         &[
             follower_actor_id.into(),
             following_id.into(),
             activity_uri.into(),
-            AUTO_ACCEPT_FOLLOWS.get().into(),
+            auto_accept.into(),
         ],
     )
-    .expect("failed to insert follow");
+    .expect("failed to insert follow")
+    .unwrap_or(false);
 
-    // If auto-accept, send an Accept back
-    if AUTO_ACCEPT_FOLLOWS.get() {
-        let base = base_url();
+    let kind = if accepted {
+        ApNotificationType::Follow
+    } else {
+        ApNotificationType::FollowRequest
+    };
+    notify(following_id, kind, follower_actor_id, None);
 
-        // Get the followed actor's info
-        let followed_username = Spi::get_one_with_args::<String>(
-            "SELECT username FROM ap_actors WHERE id = $1 AND domain IS NULL",
-            &[following_id.into()],
-        )
-        .ok()
-        .flatten();
-
-        // Only send Accept if the followed actor is local
-        if let Some(username) = followed_username {
-            let followed_uri = format!("{}/users/{}", base, username);
-            let follower_inbox = Spi::get_one_with_args::<String>(
-                "SELECT inbox_uri FROM ap_actors WHERE id = $1",
-                &[follower_actor_id.into()],
-            )
-            .unwrap()
-            .unwrap();
-
-            // Create the Accept activity
-            let accept_id = Spi::get_one::<i64>("SELECT nextval('ap_activities_id_seq')")
-                .unwrap()
-                .unwrap();
-            let accept_uri = format!("{}/activities/{}", followed_uri, accept_id);
-
-            let accept_json = json!({
-                "@context": "https://www.w3.org/ns/activitystreams",
-                "id": accept_uri,
-                "type": "Accept",
-                "actor": followed_uri,
-                "object": activity
-            });
-
-            Spi::run_with_args(
-                "INSERT INTO ap_activities (id, uri, activity_type, actor_id, object_uri,
-                    raw, local, processed)
-                 VALUES ($1, $2, 'Accept', $3, $4, $5, true, true)",
-                &[
-                    accept_id.into(),
-                    accept_uri.into(),
-                    following_id.into(),
-                    json_str(activity, "id").into(),
-                    pgrx::JsonB(accept_json).into(),
-                ],
-            )
-            .expect("failed to insert Accept activity");
+    // Send an Accept back if the followed actor is local
+    if accepted && target_is_local.unwrap_or(false) {
+        send_follow_response("Accept", following_id, follower_actor_id, activity);
+    }
+}
 
-            // Queue delivery of the Accept
-            Spi::run_with_args(
-                "INSERT INTO ap_deliveries (activity_id, inbox_uri) VALUES ($1, $2)",
-                &[accept_id.into(), follower_inbox.into()],
-            )
-            .expect("failed to queue Accept delivery");
-        }
+/// Answer a Follow addressed to a local actor with an Accept or Reject that
+/// embeds the original Follow, queued for the follower's inbox.
+/// Returns the response's URI.
+pub fn send_follow_response(
+    response_type: &str,
+    followed_id: i64,
+    follower_id: i64,
+    follow: &serde_json::Value,
+) -> String {
+    let followed_uri = Spi::get_one_with_args::<String>(
+        "SELECT uri FROM ap_actors WHERE id = $1",
+        &[followed_id.into()],
+    )
+    .expect("failed to query followed actor")
+    .expect("followed actor not found");
+
+    let follower_inbox = Spi::get_one_with_args::<String>(
+        "SELECT inbox_uri FROM ap_actors WHERE id = $1",
+        &[follower_id.into()],
+    )
+    .expect("failed to query follower")
+    .expect("follower not found");
+
+    let mut follow = follow.clone();
+    if let Some(obj) = follow.as_object_mut() {
+        obj.remove("@context");
     }
+
+    let (response_id, response_uri) = allocate_activity(&followed_uri);
+    let response_json = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": response_uri,
+        "type": response_type,
+        "actor": followed_uri,
+        "object": follow
+    });
+
+    store_local_activity(response_id, followed_id, &response_json);
+
+    Spi::run_with_args(
+        "INSERT INTO ap_deliveries (activity_id, inbox_uri) VALUES ($1, $2)
+         ON CONFLICT (activity_id, inbox_uri) DO NOTHING",
+        &[response_id.into(), follower_inbox.into()],
+    )
+    .expect("failed to queue follow response delivery");
+
+    response_uri
 }
 
-fn process_like(_activity_id: i64, actor_id: i64, object_uri: &Option<String>) {
+fn process_like(activity_id: i64, actor_id: i64, object_uri: &Option<String>) {
     let object_uri = object_uri.as_ref().expect("Like activity missing 'object'");
 
     // Look up the object
@@ -335,15 +1069,17 @@ fn process_like(_activity_id: i64, actor_id: i64, object_uri: &Option<String>) {
     if let Some(oid) = object_id {
         Spi::run_with_args(
             "INSERT INTO ap_likes (actor_id, object_id, uri)
-             VALUES ($1, $2, $3)
+             SELECT $1, $2, uri FROM ap_activities WHERE id = $3
              ON CONFLICT (actor_id, object_id) DO NOTHING",
-            &[actor_id.into(), oid.into(), Option::<String>::None.into()],
+            &[actor_id.into(), oid.into(), activity_id.into()],
         )
         .expect("failed to insert like");
+
+        notify_author(ApNotificationType::Like, actor_id, oid);
     }
 }
 
-fn process_announce(_activity_id: i64, actor_id: i64, object_uri: &Option<String>) {
+fn process_announce(activity_id: i64, actor_id: i64, object_uri: &Option<String>) {
     let object_uri = object_uri
         .as_ref()
         .expect("Announce activity missing 'object'");
@@ -358,11 +1094,13 @@ fn process_announce(_activity_id: i64, actor_id: i64, object_uri: &Option<String
     if let Some(oid) = object_id {
         Spi::run_with_args(
             "INSERT INTO ap_announces (actor_id, object_id, uri)
-             VALUES ($1, $2, $3)
+             SELECT $1, $2, uri FROM ap_activities WHERE id = $3
              ON CONFLICT (actor_id, object_id) DO NOTHING",
-            &[actor_id.into(), oid.into(), Option::<String>::None.into()],
+            &[actor_id.into(), oid.into(), activity_id.into()],
         )
         .expect("failed to insert announce");
+
+        notify_author(ApNotificationType::Announce, actor_id, oid);
     }
 }
 
@@ -403,13 +1141,30 @@ fn process_undo(actor_id: i64, activity: &serde_json::Value) {
             };
 
             if let Some(target) = target_uri {
-                Spi::run_with_args(
+                let following_id = Spi::get_one_with_args::<i64>(
                     "DELETE FROM ap_follows
                      WHERE follower_id = $1
-                     AND following_id = (SELECT id FROM ap_actors WHERE uri = $2)",
+                     AND following_id = (SELECT id FROM ap_actors WHERE uri = $2)
+                     RETURNING following_id",
                     &[actor_id.into(), target.into()],
                 )
-                .expect("failed to undo follow");
+                .ok()
+                .flatten();
+
+                if let Some(following_id) = following_id {
+                    retract(
+                        ApNotificationType::Follow,
+                        actor_id,
+                        Some(following_id),
+                        None,
+                    );
+                    retract(
+                        ApNotificationType::FollowRequest,
+                        actor_id,
+                        Some(following_id),
+                        None,
+                    );
+                }
             }
         }
         "Like" => {
@@ -431,13 +1186,19 @@ fn process_undo(actor_id: i64, activity: &serde_json::Value) {
             };
 
             if let Some(obj_uri) = object_uri {
-                Spi::run_with_args(
+                let object_id = Spi::get_one_with_args::<i64>(
                     "DELETE FROM ap_likes
                      WHERE actor_id = $1
-                     AND object_id = (SELECT id FROM ap_objects WHERE uri = $2)",
+                     AND object_id = (SELECT id FROM ap_objects WHERE uri = $2)
+                     RETURNING object_id",
                     &[actor_id.into(), obj_uri.into()],
                 )
-                .expect("failed to undo like");
+                .ok()
+                .flatten();
+
+                if let Some(object_id) = object_id {
+                    retract(ApNotificationType::Like, actor_id, None, Some(object_id));
+                }
             }
         }
         "Announce" => {
@@ -459,13 +1220,46 @@ fn process_undo(actor_id: i64, activity: &serde_json::Value) {
             };
 
             if let Some(obj_uri) = object_uri {
-                Spi::run_with_args(
+                let object_id = Spi::get_one_with_args::<i64>(
                     "DELETE FROM ap_announces
                      WHERE actor_id = $1
-                     AND object_id = (SELECT id FROM ap_objects WHERE uri = $2)",
+                     AND object_id = (SELECT id FROM ap_objects WHERE uri = $2)
+                     RETURNING object_id",
                     &[actor_id.into(), obj_uri.into()],
                 )
-                .expect("failed to undo announce");
+                .ok()
+                .flatten();
+
+                if let Some(object_id) = object_id {
+                    retract(
+                        ApNotificationType::Announce,
+                        actor_id,
+                        None,
+                        Some(object_id),
+                    );
+                }
+            }
+        }
+        "Block" => {
+            let blocked_uri = if inner.is_string() {
+                Spi::get_one_with_args::<String>(
+                    "SELECT object_uri FROM ap_activities WHERE uri = $1",
+                    &[inner.as_str().unwrap().into()],
+                )
+                .ok()
+                .flatten()
+            } else {
+                json_id(inner, "object")
+            };
+
+            if let Some(blocked) = blocked_uri {
+                Spi::run_with_args(
+                    "DELETE FROM ap_blocks
+                     WHERE actor_id = $1
+                     AND blocked_actor_id = (SELECT id FROM ap_actors WHERE uri = $2)",
+                    &[actor_id.into(), blocked.into()],
+                )
+                .expect("failed to undo block");
             }
         }
         _ => {
@@ -481,6 +1275,11 @@ fn process_create(actor_id: i64, activity: &serde_json::Value) {
         return;
     }
 
+    // Poll votes are named replies to our Questions, not posts
+    if process_vote(actor_id, inner) {
+        return;
+    }
+
     let object_type = json_str(inner, "type").unwrap_or_default();
     let pg_type = match object_type.as_str() {
         "Note" | "Article" | "Page" | "Image" | "Video" | "Audio" | "Event" | "Question"
@@ -494,7 +1293,19 @@ fn process_create(actor_id: i64, activity: &serde_json::Value) {
     let summary_val = json_str(inner, "summary");
     let url = json_str(inner, "url");
     let in_reply_to = json_str(inner, "inReplyTo");
-    let conversation = json_str(inner, "conversation").or_else(|| json_str(inner, "context"));
+    // Replies that name no conversation join their parent's
+    let conversation = json_str(inner, "conversation")
+        .or_else(|| json_str(inner, "context"))
+        .or_else(|| {
+            in_reply_to.as_ref().and_then(|parent| {
+                Spi::get_one_with_args::<String>(
+                    "SELECT conversation_uri FROM ap_objects WHERE uri = $1",
+                    &[parent.as_str().into()],
+                )
+                .ok()
+                .flatten()
+            })
+        });
     let published = json_str(inner, "published");
     let sensitive = inner
         .get("sensitive")
@@ -505,16 +1316,34 @@ fn process_create(actor_id: i64, activity: &serde_json::Value) {
         .and_then(|v| v.as_object())
         .and_then(|m| m.keys().next().cloned());
 
-    Spi::run_with_args(
+    // Addressing lives on the object; fall back to the activity's
+    let to_uris = json_str_array(inner, "to")
+        .or_else(|| json_str_array(activity, "to"))
+        .unwrap_or_default();
+    let cc_uris = json_str_array(inner, "cc")
+        .or_else(|| json_str_array(activity, "cc"))
+        .unwrap_or_default();
+
+    let followers_uri = Spi::get_one_with_args::<String>(
+        "SELECT followers_uri FROM ap_actors WHERE id = $1",
+        &[actor_id.into()],
+    )
+    .ok()
+    .flatten();
+    let visibility = classify_visibility(&to_uris, &cc_uris, followers_uri.as_deref());
+    let recipients = explicit_recipients(&to_uris, &cc_uris, followers_uri.as_deref());
+
+    let stored_id = Spi::get_one_with_args::<i64>(
         "INSERT INTO ap_objects (uri, object_type, actor_id, content, content_text,
             summary, url, in_reply_to_uri, conversation_uri, visibility,
-            sensitive, language, published_at, raw)
-         VALUES ($1, $2::ApObjectType, $3, $4, $5, $6, $7, $8, $9, 'Public',
-            $10, $11, CASE WHEN $12 IS NOT NULL THEN $12::timestamptz ELSE now() END,
-            $13)
-         ON CONFLICT (uri) DO NOTHING",
+            sensitive, language, published_at, raw, to_uris, cc_uris)
+         VALUES ($1, $2::ApObjectType, $3, $4, $5, $6, $7, $8, $9, $10,
+            $11, $12, CASE WHEN $13 IS NOT NULL THEN $13::timestamptz ELSE now() END,
+            $14, $15, $16)
+         ON CONFLICT (uri) DO NOTHING
+         RETURNING id",
         &[
-            object_uri.into(),
+            object_uri.clone().into(),
             pg_type.to_string().into(),
             actor_id.into(),
             content.into(),
@@ -523,13 +1352,29 @@ fn process_create(actor_id: i64, activity: &serde_json::Value) {
             url.into(),
             in_reply_to.into(),
             conversation.into(),
+            visibility.into(),
             sensitive.into(),
             language.into(),
             published.into(),
             pgrx::JsonB(inner.clone()).into(),
+            to_uris.into(),
+            cc_uris.into(),
         ],
     )
-    .expect("failed to insert remote object");
+    .ok()
+    .flatten();
+
+    // A known object (a redelivery, or a new Create for it) is left as is
+    if let Some(object_id) = stored_id {
+        record_recipients(object_id, &recipients);
+        link_pins(object_id, actor_id, &object_uri);
+        sync_object_tags(object_id, inner.get("tag"));
+        sync_attachments(object_id, inner.get("attachment"));
+        if pg_type == "Question" {
+            sync_remote_poll(object_id, inner);
+        }
+        notify_audience(object_id, actor_id);
+    }
 }
 
 fn process_update(actor_id: i64, activity: &serde_json::Value) {
@@ -539,6 +1384,18 @@ fn process_update(actor_id: i64, activity: &serde_json::Value) {
         return;
     }
 
+    // An actor updating its own profile (e.g. after rotating its keys)
+    let is_actor = matches!(
+        json_str(inner, "type").as_deref(),
+        Some("Person" | "Group" | "Application" | "Service" | "Organization")
+    );
+    if is_actor {
+        if json_str(inner, "id") == json_str(activity, "actor") {
+            crate::actors::ap_upsert_remote_actor(pgrx::Json(inner.clone()));
+        }
+        return;
+    }
+
     let object_uri = json_str(inner, "id").expect("object missing 'id'");
     let content = json_str(inner, "content");
     let content_text = content.as_ref().map(|c| strip_html(c));
@@ -546,7 +1403,7 @@ fn process_update(actor_id: i64, activity: &serde_json::Value) {
     let sensitive = inner.get("sensitive").and_then(|v| v.as_bool());
 
     // Only update if the object belongs to this actor
-    Spi::run_with_args(
+    let updated_id = Spi::get_one_with_args::<i64>(
         "UPDATE ap_objects SET
             content = COALESCE($2, content),
             content_text = COALESCE($3, content_text),
@@ -554,7 +1411,8 @@ fn process_update(actor_id: i64, activity: &serde_json::Value) {
             sensitive = COALESCE($5, sensitive),
             edited_at = now(),
             raw = $6
-         WHERE uri = $1 AND actor_id = $7",
+         WHERE uri = $1 AND actor_id = $7
+         RETURNING id",
         &[
             object_uri.into(),
             content.into(),
@@ -565,7 +1423,17 @@ fn process_update(actor_id: i64, activity: &serde_json::Value) {
             actor_id.into(),
         ],
     )
-    .expect("failed to update object");
+    .ok()
+    .flatten();
+
+    // Updates carry the full object, so its tags and media replace the old ones
+    if let Some(object_id) = updated_id {
+        sync_object_tags(object_id, inner.get("tag"));
+        sync_attachments(object_id, inner.get("attachment"));
+        if json_str(inner, "type").as_deref() == Some("Question") {
+            sync_remote_poll(object_id, inner);
+        }
+    }
 }
 
 fn process_delete(actor_id: i64, object_uri: &Option<String>) {
@@ -574,15 +1442,21 @@ fn process_delete(actor_id: i64, object_uri: &Option<String>) {
         .expect("Delete activity missing 'object'");
 
     // Soft-delete: only if owned by this actor
-    Spi::run_with_args(
+    let object_id = Spi::get_one_with_args::<i64>(
         "UPDATE ap_objects SET deleted_at = now(), content = NULL, content_text = NULL
-         WHERE uri = $1 AND actor_id = $2",
+         WHERE uri = $1 AND actor_id = $2
+         RETURNING id",
         &[object_uri.clone().into(), actor_id.into()],
     )
-    .expect("failed to delete object");
+    .ok()
+    .flatten();
+
+    if let Some(object_id) = object_id {
+        forget_deleted_object(object_id);
+    }
 }
 
-fn process_accept(_actor_id: i64, activity: &serde_json::Value) {
+fn process_accept(actor_id: i64, activity: &serde_json::Value) {
     let inner = activity.get("object").expect("Accept missing 'object'");
 
     // The object of an Accept is typically the Follow activity that was accepted
@@ -593,16 +1467,17 @@ fn process_accept(_actor_id: i64, activity: &serde_json::Value) {
     };
 
     if let Some(uri) = follow_uri {
-        // Accept the follow using the Follow activity's URI
+        // Accept the follow using the Follow activity's URI; only the
+        // followed actor can accept it
         Spi::run_with_args(
-            "UPDATE ap_follows SET accepted = true WHERE uri = $1",
-            &[uri.into()],
+            "UPDATE ap_follows SET accepted = true WHERE uri = $1 AND following_id = $2",
+            &[uri.into(), actor_id.into()],
         )
         .expect("failed to accept follow");
     }
 }
 
-fn process_reject(_actor_id: i64, activity: &serde_json::Value) {
+fn process_reject(actor_id: i64, activity: &serde_json::Value) {
     let inner = activity.get("object").expect("Reject missing 'object'");
 
     let follow_uri = if inner.is_string() {
@@ -612,9 +1487,13 @@ fn process_reject(_actor_id: i64, activity: &serde_json::Value) {
     };
 
     if let Some(uri) = follow_uri {
-        // Remove the follow
-        Spi::run_with_args("DELETE FROM ap_follows WHERE uri = $1", &[uri.into()])
-            .expect("failed to reject follow");
+        // Remove the follow (pending or accepted); only the followed actor
+        // can reject it
+        Spi::run_with_args(
+            "DELETE FROM ap_follows WHERE uri = $1 AND following_id = $2",
+            &[uri.into(), actor_id.into()],
+        )
+        .expect("failed to reject follow");
     }
 }
 
@@ -650,10 +1529,69 @@ fn process_block(_activity_id: i64, actor_id: i64, object_uri: &Option<String>)
     }
 }
 
+/// Whether an inbound activity targets a local actor who has blocked its
+/// sender: it acts on them or one of their posts (Follow, Like, Announce, ...)
+/// or is a non-public activity addressed to them.
+fn is_blocked_by_recipient(
+    actor_id: i64,
+    activity: &serde_json::Value,
+    object_uri: Option<&str>,
+) -> bool {
+    let mut addressed: Vec<String> = Vec::new();
+    let inner = activity.get("object").filter(|v| v.is_object());
+    for source in std::iter::once(activity).chain(inner) {
+        for key in ["to", "cc"] {
+            addressed.extend(json_str_array(source, key).unwrap_or_default());
+        }
+    }
+    let public = addressed.iter().any(|uri| is_public_uri(uri));
+
+    Spi::get_one_with_args::<bool>(
+        "SELECT EXISTS (
+            SELECT 1 FROM ap_blocks b
+            JOIN ap_actors me ON me.id = b.actor_id AND me.domain IS NULL
+            WHERE b.blocked_actor_id = $1
+            AND (
+                me.uri = $2
+                OR EXISTS (SELECT 1 FROM ap_objects o WHERE o.uri = $2 AND o.actor_id = me.id)
+                OR (NOT $3 AND me.uri = ANY($4))
+            )
+         )",
+        &[
+            actor_id.into(),
+            object_uri.into(),
+            public.into(),
+            addressed.into(),
+        ],
+    )
+    .unwrap_or(Some(false))
+    .unwrap_or(false)
+}
+
 // =============================================================================
 // Helpers
 // =============================================================================
 
+/// Abort inbox processing because the request's signature cannot be trusted.
+/// Uses a dedicated SQLSTATE so callers can answer 401 rather than 500.
+fn reject_signature(reason: &str) -> ! {
+    ereport!(
+        ERROR,
+        PgSqlErrorCode::ERRCODE_INVALID_AUTHORIZATION_SPECIFICATION,
+        format!("HTTP signature rejected: {}", reason)
+    );
+}
+
+/// Abort inbox processing because the activity's integrity proof cannot be
+/// trusted. Uses the same SQLSTATE as a rejected HTTP signature.
+fn reject_proof(reason: &str) -> ! {
+    ereport!(
+        ERROR,
+        PgSqlErrorCode::ERRCODE_INVALID_AUTHORIZATION_SPECIFICATION,
+        format!("integrity proof rejected: {}", reason)
+    );
+}
+
 /// Resolve an actor URI to a database ID, creating a stub if needed.
 fn resolve_actor_id(actor_uri: &str) -> i64 {
     // Try to find existing
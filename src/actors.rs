@@ -1,15 +1,22 @@
 use pgrx::prelude::*;
 use serde_json::json;
 
-use crate::crypto::generate_keypair;
+use crate::activities::{allocate_activity, store_local_activity};
+use crate::crypto::{
+    ap_ed25519_multikey, ed25519_pem_from_multikey, generate_ed25519_keypair, generate_keypair,
+    public_key_type, seal_private_key,
+};
+use crate::delivery::queue_follower_deliveries;
 use crate::guc::base_url;
-use crate::util::{json_str, json_str_nested, parse_domain};
+use crate::types::ApKeyType;
+use crate::util::{json_str, json_str_nested, parse_domain, AS_PUBLIC};
 
 // =============================================================================
 // Local actor creation
 // =============================================================================
 
-/// Create a local actor with a generated RSA keypair.
+/// Create a local actor with generated RSA and Ed25519 keypairs. The
+/// private keys are stored encrypted when a master key is configured.
 /// Returns the actor's URI.
 #[pg_extern]
 fn ap_create_local_actor(
@@ -26,8 +33,10 @@ fn ap_create_local_actor(
     let featured = format!("{}/collections/featured", uri);
     let shared_inbox = format!("{}/inbox", base);
     let key_id = format!("{}#main-key", uri);
+    let ed25519_key_id = format!("{}#ed25519-key", uri);
 
     let (public_pem, private_pem) = generate_keypair();
+    let (ed25519_public_pem, ed25519_private_pem) = generate_ed25519_keypair();
 
     Spi::run_with_args(
         "INSERT INTO ap_actors (uri, actor_type, username, display_name, summary,
@@ -49,29 +58,113 @@ fn ap_create_local_actor(
     .expect("failed to insert actor");
 
     Spi::run_with_args(
-        "INSERT INTO ap_keys (actor_id, key_id, public_key_pem, private_key_pem)
-         VALUES ((SELECT id FROM ap_actors WHERE uri = $1), $2, $3, $4)",
+        "INSERT INTO ap_keys (actor_id, key_id, key_type, public_key_pem, private_key_pem)
+         SELECT a.id, k.key_id, k.key_type::ApKeyType, k.public_key_pem, k.private_key_pem
+         FROM ap_actors a, (VALUES ($2, 'Rsa', $3, $4), ($5, 'Ed25519', $6, $7))
+            AS k (key_id, key_type, public_key_pem, private_key_pem)
+         WHERE a.uri = $1",
         &[
             uri.clone().into(),
             key_id.into(),
             public_pem.into(),
-            private_pem.into(),
+            seal_private_key(&private_pem).into(),
+            ed25519_key_id.into(),
+            ed25519_public_pem.into(),
+            seal_private_key(&ed25519_private_pem).into(),
         ],
     )
-    .expect("failed to insert keypair");
+    .expect("failed to insert keypairs");
 
     uri
 }
 
+/// Look up a local actor by username, returning its id and URI.
+/// Errors if no such local actor exists.
+pub fn local_actor(username: &str) -> (i64, String) {
+    let actor = Spi::get_two_with_args::<i64, String>(
+        "SELECT id, uri FROM ap_actors WHERE username = $1 AND domain IS NULL",
+        &[username.into()],
+    );
+
+    match actor {
+        Ok((Some(id), Some(uri))) => (id, uri),
+        _ => pgrx::error!("local actor '{}' not found", username),
+    }
+}
+
+// =============================================================================
+// Key rotation
+// =============================================================================
+
+/// Rotate a local actor's keys: generate new RSA and Ed25519 keypairs and
+/// retire the current ones, which stop signing at once but keep verifying
+/// for grace_days. An Update of the actor is federated to its followers so
+/// remote servers pick up the new keys.
+/// Returns the Update activity's URI.
+#[pg_extern]
+fn ap_rotate_actor_key(username: &str, grace_days: default!(i32, 7)) -> String {
+    let (actor_id, actor_uri) = local_actor(username);
+
+    let (public_pem, private_pem) = generate_keypair();
+    let (ed25519_public_pem, ed25519_private_pem) = generate_ed25519_keypair();
+
+    Spi::run_with_args(
+        "UPDATE ap_keys SET expires_at = now() + make_interval(days => $2), private_key_pem = NULL
+         WHERE actor_id = $1 AND expires_at IS NULL",
+        &[actor_id.into(), grace_days.into()],
+    )
+    .expect("failed to retire keys");
+
+    // New key ids take the row id, e.g. #main-key-42, as the old ones are kept
+    Spi::run_with_args(
+        "INSERT INTO ap_keys (id, actor_id, key_id, key_type, public_key_pem, private_key_pem)
+         SELECT id, $1, $2 || '#' || fragment || '-' || id, key_type::ApKeyType,
+            public_key_pem, private_key_pem
+         FROM (
+            SELECT nextval('ap_keys_id_seq') AS id, k.*
+            FROM (VALUES ('main-key', 'Rsa', $3, $4), ('ed25519-key', 'Ed25519', $5, $6))
+                AS k (fragment, key_type, public_key_pem, private_key_pem)
+         ) keys",
+        &[
+            actor_id.into(),
+            actor_uri.clone().into(),
+            public_pem.into(),
+            seal_private_key(&private_pem).into(),
+            ed25519_public_pem.into(),
+            seal_private_key(&ed25519_private_pem).into(),
+        ],
+    )
+    .expect("failed to insert keypairs");
+
+    let mut actor = ap_serialize_actor(username).0;
+    let context = actor.as_object_mut().unwrap().remove("@context");
+
+    let (activity_id, activity_uri) = allocate_activity(&actor_uri);
+    let update_json = json!({
+        "@context": context,
+        "id": activity_uri,
+        "type": "Update",
+        "actor": actor_uri,
+        "to": [AS_PUBLIC],
+        "cc": actor.get("followers").map(|f| vec![f.clone()]).unwrap_or_default(),
+        "object": actor
+    });
+
+    store_local_activity(activity_id, actor_id, &update_json);
+    queue_follower_deliveries(activity_id, actor_id);
+
+    activity_uri
+}
+
 // =============================================================================
 // Remote actor upsert
 // =============================================================================
 
 /// Upsert a remote actor from raw ActivityStreams JSON.
 /// Parses the JSON to extract fields, inserts or updates the actor row,
-/// and stores/updates the public key if present.
+/// and stores/updates the public key if present and owned by the actor.
 #[pg_extern]
-fn ap_upsert_remote_actor(actor_json: pgrx::Json) -> String {
+pub fn ap_upsert_remote_actor(actor_json: pgrx::Json) -> String {
     let obj = &actor_json.0;
 
     let uri = json_str(obj, "id").expect("actor JSON missing 'id'");
@@ -151,26 +244,89 @@ fn ap_upsert_remote_actor(actor_json: pgrx::Json) -> String {
     )
     .expect("failed to upsert remote actor");
 
-    // Upsert public key if present
-    if let Some(pk) = obj.get("publicKey") {
+    // Link objects that addressed or mentioned this actor before we knew about it
+    Spi::run_with_args(
+        "UPDATE ap_object_recipients SET actor_id = (SELECT id FROM ap_actors WHERE uri = $1)
+         WHERE actor_uri = $1 AND actor_id IS NULL",
+        &[uri.clone().into()],
+    )
+    .expect("failed to link object recipients");
+
+    Spi::run_with_args(
+        "UPDATE ap_mentions SET actor_id = (SELECT id FROM ap_actors WHERE uri = $1)
+         WHERE actor_uri = $1 AND actor_id IS NULL",
+        &[uri.clone().into()],
+    )
+    .expect("failed to link mentions");
+
+    // Upsert public key if present and owned by the actor, typed by the key
+    // its PEM holds (some servers publish Ed25519 keys here; anything we
+    // cannot parse is assumed RSA). A key id already held by another actor
+    // is left alone.
+    if let Some(pk) = obj
+        .get("publicKey")
+        .filter(|pk| json_str(pk, "owner").as_deref() == Some(uri.as_str()))
+    {
         let key_id = json_str(pk, "id");
         let public_key_pem = json_str(pk, "publicKeyPem");
 
         if let (Some(key_id), Some(public_key_pem)) = (key_id, public_key_pem) {
+            let key_type = public_key_type(&public_key_pem).unwrap_or(ApKeyType::Rsa);
             Spi::run_with_args(
-                "INSERT INTO ap_keys (actor_id, key_id, public_key_pem)
-                 VALUES ((SELECT id FROM ap_actors WHERE uri = $1), $2, $3)
+                "INSERT INTO ap_keys (actor_id, key_id, key_type, public_key_pem)
+                 VALUES ((SELECT id FROM ap_actors WHERE uri = $1), $2, $3, $4)
                  ON CONFLICT (key_id) DO UPDATE SET
-                    public_key_pem = EXCLUDED.public_key_pem",
-                &[uri.clone().into(), key_id.into(), public_key_pem.into()],
+                    key_type = EXCLUDED.key_type,
+                    public_key_pem = EXCLUDED.public_key_pem
+                 WHERE ap_keys.actor_id = EXCLUDED.actor_id",
+                &[
+                    uri.clone().into(),
+                    key_id.into(),
+                    key_type.into(),
+                    public_key_pem.into(),
+                ],
             )
             .expect("failed to upsert public key");
         }
     }
 
+    // Upsert Ed25519 Multikeys the actor controls (FEP-521a)
+    for method in json_objects(obj.get("assertionMethod")) {
+        if json_str(method, "type").as_deref() != Some("Multikey")
+            || json_str(method, "controller").as_deref() != Some(uri.as_str())
+        {
+            continue;
+        }
+        let key_id = json_str(method, "id");
+        let public_key_pem =
+            json_str(method, "publicKeyMultibase").and_then(|m| ed25519_pem_from_multikey(&m));
+
+        if let (Some(key_id), Some(public_key_pem)) = (key_id, public_key_pem) {
+            Spi::run_with_args(
+                "INSERT INTO ap_keys (actor_id, key_id, key_type, public_key_pem)
+                 VALUES ((SELECT id FROM ap_actors WHERE uri = $1), $2, 'Ed25519', $3)
+                 ON CONFLICT (key_id) DO UPDATE SET
+                    key_type = EXCLUDED.key_type,
+                    public_key_pem = EXCLUDED.public_key_pem
+                 WHERE ap_keys.actor_id = EXCLUDED.actor_id",
+                &[uri.clone().into(), key_id.into(), public_key_pem.into()],
+            )
+            .expect("failed to upsert Multikey");
+        }
+    }
+
     uri
 }
 
+/// The objects in a JSON value that may be a single object or an array.
+fn json_objects(value: Option<&serde_json::Value>) -> Vec<&serde_json::Value> {
+    match value {
+        Some(serde_json::Value::Array(items)) => items.iter().filter(|v| v.is_object()).collect(),
+        Some(v) if v.is_object() => vec![v],
+        _ => Vec::new(),
+    }
+}
+
 // =============================================================================
 // Actor serialization
 // =============================================================================
@@ -201,9 +357,19 @@ fn ap_serialize_actor(username: &str) -> pgrx::Json {
             'memorial', a.memorial,
             'created_at', a.created_at,
             'public_key_pem', k.public_key_pem,
-            'key_id', k.key_id
+            'key_id', k.key_id,
+            'ed25519_keys', (
+                SELECT json_agg(json_build_object('key_id', e.key_id, 'public_key_pem', e.public_key_pem)
+                    ORDER BY e.id)
+                FROM ap_keys e WHERE e.actor_id = a.id AND e.key_type = 'Ed25519'
+                AND (e.expires_at IS NULL OR e.expires_at > now())
+            )
         )::json FROM ap_actors a
-        LEFT JOIN ap_keys k ON k.actor_id = a.id
+        LEFT JOIN LATERAL (
+            SELECT key_id, public_key_pem FROM ap_keys
+            WHERE actor_id = a.id AND key_type = 'Rsa' AND expires_at IS NULL
+            ORDER BY id DESC LIMIT 1
+        ) k ON true
         WHERE a.username = $1 AND a.domain IS NULL",
         &[username.into()],
     )
@@ -242,6 +408,7 @@ fn ap_serialize_actor(username: &str) -> pgrx::Json {
         "@context": [
             "https://www.w3.org/ns/activitystreams",
             "https://w3id.org/security/v1",
+            "https://w3id.org/security/multikey/v1",
             {
                 "manuallyApprovesFollowers": "as:manuallyApprovesFollowers",
                 "toot": "http://joinmastodon.org/ns#",
@@ -291,6 +458,26 @@ fn ap_serialize_actor(username: &str) -> pgrx::Json {
         );
     }
 
+    let multikeys: Vec<_> = r
+        .get("ed25519_keys")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|k| {
+            let kid = json_str(k, "key_id")?;
+            let multibase = ap_ed25519_multikey(&json_str(k, "public_key_pem")?)?;
+            Some(json!({
+                "id": kid,
+                "type": "Multikey",
+                "controller": uri,
+                "publicKeyMultibase": multibase
+            }))
+        })
+        .collect();
+    if !multikeys.is_empty() {
+        obj.insert("assertionMethod".into(), json!(multikeys));
+    }
+
     if let Some(si) = shared_inbox {
         obj.insert("endpoints".into(), json!({ "sharedInbox": si }));
     }
@@ -1,5 +1,9 @@
 use pgrx::prelude::*;
 
+use crate::actors::local_actor;
+use crate::crypto::{encrypt_private_key, is_encrypted_private_key};
+use crate::guc::master_key;
+
 // =============================================================================
 // Instance (domain) blocking
 // =============================================================================
@@ -155,13 +159,33 @@ fn ap_search_objects(
 // Home timeline
 // =============================================================================
 
-/// Get the home timeline for a local user: public objects from actors they follow,
-/// plus their own posts, in reverse chronological order.
+/// A timeline row: entry id, object URI, type, content, author URI, author
+/// username, published time, and the booster's URI for boosts.
+type TimelineRow = (
+    i64,
+    String,
+    String,
+    Option<String>,
+    String,
+    String,
+    Option<TimestampWithTimeZone>,
+    Option<String>,
+);
+
+/// Get the home timeline for a local user: posts and boosts by the actors they
+/// follow and by themselves, newest first, limited to what they may see.
+///
+/// Rows are keyed by the id of the Create or Announce that put the post on the
+/// timeline, so a boosted post appears once, at its latest boost. Page with
+/// `max_id` (older than), `since_id` (newer than, newest first) or `min_id`
+/// (the entries immediately newer than).
 #[pg_extern]
 fn ap_home_timeline(
     username: &str,
     max_results: default!(i32, 20),
-    before_id: default!(Option<i64>, "NULL"),
+    max_id: default!(Option<i64>, "NULL"),
+    since_id: default!(Option<i64>, "NULL"),
+    min_id: default!(Option<i64>, "NULL"),
 ) -> TableIterator<
     'static,
     (
@@ -172,52 +196,437 @@ fn ap_home_timeline(
         name!(actor_uri, String),
         name!(actor_username, String),
         name!(published_at, Option<TimestampWithTimeZone>),
+        name!(boosted_by, Option<String>),
     ),
 > {
+    let (actor_id, _) = local_actor(username);
+
+    TableIterator::new(timeline_rows(
+        "SELECT following_id FROM ap_follows WHERE follower_id = $1 AND accepted
+         UNION SELECT $1",
+        actor_id,
+        None,
+        max_results,
+        (max_id, since_id, min_id),
+    ))
+}
+
+/// How many Creates and Announces a timeline page considers per row it
+/// returns, leaving room for duplicate boosts and hidden or muted posts.
+const TIMELINE_OVERFETCH: i64 = 4;
+
+/// Query a timeline of the posts and boosts by the actors `sources_sql`
+/// selects, as seen by `viewer_id`. `sources_sql` may use `$1` (the viewer)
+/// and `$6` (`source_arg`). `page` is (max_id, since_id, min_id).
+///
+/// Only the newest `max_results * TIMELINE_OVERFETCH` entries within the
+/// page bounds are read (via `idx_activities_actor_type`); boosts are then
+/// narrowed to each post's latest entry and visibility is checked on that
+/// small set, so a post boosted again after `max_id` still shows on older
+/// pages at its earlier entry.
+fn timeline_rows(
+    sources_sql: &str,
+    viewer_id: i64,
+    source_arg: Option<i64>,
+    max_results: i32,
+    page: (Option<i64>, Option<i64>, Option<i64>),
+) -> Vec<TimelineRow> {
+    let (max_id, since_id, min_id) = page;
+
+    let query = format!(
+        "WITH sources (actor_id) AS ({}),
+         candidates AS (
+            SELECT c.id, c.actor_id, c.activity_type, c.uri, c.object_uri
+            FROM ap_activities c
+            WHERE c.actor_id IN (SELECT actor_id FROM sources)
+            AND c.activity_type IN ('Create', 'Announce')
+            AND ($3::bigint IS NULL OR c.id < $3)
+            AND ($4::bigint IS NULL OR c.id > $4)
+            AND ($5::bigint IS NULL OR c.id > $5)
+            ORDER BY CASE WHEN $5::bigint IS NOT NULL THEN c.id END, c.id DESC
+            LIMIT $7
+         ),
+         entries AS (
+            SELECT DISTINCT ON (e.object_id) e.entry_id, e.object_id, e.boosted_by
+            FROM (
+                SELECT c.id AS entry_id, o.id AS object_id, NULL::bigint AS boosted_by
+                FROM candidates c
+                JOIN ap_objects o ON o.uri = c.object_uri AND o.actor_id = c.actor_id
+                WHERE c.activity_type = 'Create'
+                UNION ALL
+                SELECT c.id, b.object_id, b.actor_id
+                FROM candidates c
+                JOIN ap_announces b ON b.uri = c.uri
+                WHERE c.activity_type = 'Announce'
+            ) e
+            ORDER BY e.object_id, e.entry_id DESC
+         ),
+         page AS (
+            SELECT e.* FROM entries e
+            WHERE ap_can_view(e.object_id, $1)
+            AND NOT EXISTS (
+                SELECT 1 FROM ap_blocks bl
+                JOIN ap_actors ba ON ba.id = e.boosted_by
+                WHERE (bl.actor_id = $1 AND bl.blocked_actor_id = ba.id)
+                   OR (bl.blocked_domain = ba.domain AND (bl.actor_id IS NULL OR bl.actor_id = $1))
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM ap_mutes mu
+                JOIN ap_objects mo ON mo.id = e.object_id
+                WHERE mu.actor_id = $1
+                AND mu.muted_actor_id IN (mo.actor_id, e.boosted_by)
+                AND (mu.expires_at IS NULL OR mu.expires_at > now())
+            )
+            ORDER BY CASE WHEN $5::bigint IS NOT NULL THEN e.entry_id END, e.entry_id DESC
+            LIMIT $2
+         )
+         SELECT p.entry_id, o.uri, o.object_type::text, o.content, a.uri, a.username,
+                o.published_at, ba.uri
+         FROM page p
+         JOIN ap_objects o ON o.id = p.object_id
+         JOIN ap_actors a ON a.id = o.actor_id
+         LEFT JOIN ap_actors ba ON ba.id = p.boosted_by
+         ORDER BY p.entry_id DESC",
+        sources_sql
+    );
+
+    Spi::connect(|client| {
+        let mut results = Vec::new();
+
+        let tup_table = client
+            .select(
+                &query,
+                None,
+                &[
+                    viewer_id.into(),
+                    max_results.into(),
+                    max_id.into(),
+                    since_id.into(),
+                    min_id.into(),
+                    source_arg.into(),
+                    (i64::from(max_results) * TIMELINE_OVERFETCH).into(),
+                ],
+            )
+            .expect("failed to query timeline");
+
+        for row in tup_table {
+            let id: i64 = row
+                .get_datum_by_ordinal(1)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let uri: String = row
+                .get_datum_by_ordinal(2)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let object_type: String = row
+                .get_datum_by_ordinal(3)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let content: Option<String> = row.get_datum_by_ordinal(4).unwrap().value().unwrap();
+            let actor_uri: String = row
+                .get_datum_by_ordinal(5)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let actor_username: String = row
+                .get_datum_by_ordinal(6)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let published_at: Option<TimestampWithTimeZone> =
+                row.get_datum_by_ordinal(7).unwrap().value().unwrap();
+            let boosted_by: Option<String> = row.get_datum_by_ordinal(8).unwrap().value().unwrap();
+
+            results.push((
+                id,
+                uri,
+                object_type,
+                content,
+                actor_uri,
+                actor_username,
+                published_at,
+                boosted_by,
+            ));
+        }
+
+        results
+    })
+}
+
+// =============================================================================
+// Lists
+// =============================================================================
+
+/// Create a list for a local actor. Returns the list's id.
+#[pg_extern]
+fn ap_create_list(username: &str, title: &str) -> i64 {
+    let (actor_id, _) = local_actor(username);
+
+    if title.trim().is_empty() {
+        pgrx::error!("list title must not be empty");
+    }
+
+    Spi::get_one_with_args::<i64>(
+        "INSERT INTO ap_lists (actor_id, title) VALUES ($1, $2) RETURNING id",
+        &[actor_id.into(), title.trim().into()],
+    )
+    .expect("failed to create list")
+    .expect("list insert returned no id")
+}
+
+/// Rename one of a local actor's lists.
+#[pg_extern]
+fn ap_rename_list(username: &str, list_id: i64, title: &str) {
+    let (actor_id, _) = local_actor(username);
+    owned_list(actor_id, list_id);
+
+    if title.trim().is_empty() {
+        pgrx::error!("list title must not be empty");
+    }
+
+    Spi::run_with_args(
+        "UPDATE ap_lists SET title = $2 WHERE id = $1",
+        &[list_id.into(), title.trim().into()],
+    )
+    .expect("failed to rename list");
+}
+
+/// Delete one of a local actor's lists.
+#[pg_extern]
+fn ap_delete_list(username: &str, list_id: i64) {
+    let (actor_id, _) = local_actor(username);
+    owned_list(actor_id, list_id);
+
+    Spi::run_with_args("DELETE FROM ap_lists WHERE id = $1", &[list_id.into()])
+        .expect("failed to delete list");
+}
+
+/// List a local actor's lists with their member counts.
+#[pg_extern]
+fn ap_lists(
+    username: &str,
+) -> TableIterator<'static, (name!(id, i64), name!(title, String), name!(members, i64))> {
+    let (actor_id, _) = local_actor(username);
+
     let rows: Vec<_> = Spi::connect(|client| {
         let mut results = Vec::new();
 
-        let query = if before_id.is_some() {
-            "SELECT o.id, o.uri, o.object_type::text, o.content, a.uri, a.username, o.published_at
-             FROM ap_objects o
-             JOIN ap_actors a ON a.id = o.actor_id
-             WHERE o.deleted_at IS NULL
-             AND o.id < $3
-             AND (
-                 a.id IN (
-                     SELECT f.following_id FROM ap_follows f
-                     JOIN ap_actors me ON me.id = f.follower_id
-                     WHERE me.username = $1 AND me.domain IS NULL AND f.accepted = true
-                 )
-                 OR (a.username = $1 AND a.domain IS NULL)
-             )
-             ORDER BY o.published_at DESC NULLS LAST
-             LIMIT $2"
-        } else {
-            "SELECT o.id, o.uri, o.object_type::text, o.content, a.uri, a.username, o.published_at
-             FROM ap_objects o
-             JOIN ap_actors a ON a.id = o.actor_id
-             WHERE o.deleted_at IS NULL
-             AND ($3::bigint IS NULL OR true)
-             AND (
-                 a.id IN (
-                     SELECT f.following_id FROM ap_follows f
-                     JOIN ap_actors me ON me.id = f.follower_id
-                     WHERE me.username = $1 AND me.domain IS NULL AND f.accepted = true
-                 )
-                 OR (a.username = $1 AND a.domain IS NULL)
-             )
-             ORDER BY o.published_at DESC NULLS LAST
-             LIMIT $2"
-        };
+        let tup_table = client
+            .select(
+                "SELECT l.id, l.title, count(m.actor_id)
+                 FROM ap_lists l
+                 LEFT JOIN ap_list_members m ON m.list_id = l.id
+                 WHERE l.actor_id = $1
+                 GROUP BY l.id
+                 ORDER BY l.title, l.id",
+                None,
+                &[actor_id.into()],
+            )
+            .expect("failed to query lists");
+
+        for row in tup_table {
+            let id: i64 = row
+                .get_datum_by_ordinal(1)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let title: String = row
+                .get_datum_by_ordinal(2)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let members: i64 = row
+                .get_datum_by_ordinal(3)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+
+            results.push((id, title, members));
+        }
+
+        results
+    });
+
+    TableIterator::new(rows)
+}
+
+/// Add an actor the list's owner follows to the list. Returns false if they
+/// were already a member.
+#[pg_extern]
+fn ap_add_to_list(username: &str, list_id: i64, actor_uri: &str) -> bool {
+    let (actor_id, _) = local_actor(username);
+    owned_list(actor_id, list_id);
+
+    let member_id = Spi::get_one_with_args::<i64>(
+        "SELECT a.id FROM ap_actors a
+         JOIN ap_follows f ON f.following_id = a.id
+         WHERE a.uri = $1 AND f.follower_id = $2 AND f.accepted",
+        &[actor_uri.into(), actor_id.into()],
+    )
+    .ok()
+    .flatten()
+    .unwrap_or_else(|| pgrx::error!("'{}' is not followed by '{}'", actor_uri, username));
+
+    Spi::get_one_with_args::<bool>(
+        "INSERT INTO ap_list_members (list_id, actor_id) VALUES ($1, $2)
+         ON CONFLICT DO NOTHING
+         RETURNING true",
+        &[list_id.into(), member_id.into()],
+    )
+    .ok()
+    .flatten()
+    .unwrap_or(false)
+}
+
+/// Remove an actor from one of a local actor's lists. Returns false if they
+/// were not a member.
+#[pg_extern]
+fn ap_remove_from_list(username: &str, list_id: i64, actor_uri: &str) -> bool {
+    let (actor_id, _) = local_actor(username);
+    owned_list(actor_id, list_id);
+
+    Spi::get_one_with_args::<bool>(
+        "DELETE FROM ap_list_members
+         WHERE list_id = $1 AND actor_id = (SELECT id FROM ap_actors WHERE uri = $2)
+         RETURNING true",
+        &[list_id.into(), actor_uri.into()],
+    )
+    .ok()
+    .flatten()
+    .unwrap_or(false)
+}
+
+/// Get the URIs of the members of one of a local actor's lists.
+#[pg_extern]
+fn ap_list_members(username: &str, list_id: i64) -> Vec<String> {
+    let (actor_id, _) = local_actor(username);
+    owned_list(actor_id, list_id);
+
+    Spi::get_one_with_args::<Vec<String>>(
+        "SELECT coalesce(array_agg(a.uri ORDER BY a.uri), '{}')
+         FROM ap_list_members m
+         JOIN ap_actors a ON a.id = m.actor_id
+         WHERE m.list_id = $1",
+        &[list_id.into()],
+    )
+    .expect("failed to query list members")
+    .unwrap_or_default()
+}
+
+/// Get a list's timeline: posts and boosts by its members, with the same
+/// visibility filtering, ids and paging as `ap_home_timeline`.
+#[pg_extern]
+fn ap_list_timeline(
+    username: &str,
+    list_id: i64,
+    max_results: default!(i32, 20),
+    max_id: default!(Option<i64>, "NULL"),
+    since_id: default!(Option<i64>, "NULL"),
+    min_id: default!(Option<i64>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(id, i64),
+        name!(uri, String),
+        name!(object_type, String),
+        name!(content, Option<String>),
+        name!(actor_uri, String),
+        name!(actor_username, String),
+        name!(published_at, Option<TimestampWithTimeZone>),
+        name!(boosted_by, Option<String>),
+    ),
+> {
+    let (actor_id, _) = local_actor(username);
+    owned_list(actor_id, list_id);
+
+    TableIterator::new(timeline_rows(
+        "SELECT actor_id FROM ap_list_members WHERE list_id = $6",
+        actor_id,
+        Some(list_id),
+        max_results,
+        (max_id, since_id, min_id),
+    ))
+}
+
+/// Error unless `list_id` belongs to the local actor.
+fn owned_list(actor_id: i64, list_id: i64) {
+    let owned = Spi::get_one_with_args::<bool>(
+        "SELECT EXISTS(SELECT 1 FROM ap_lists WHERE id = $1 AND actor_id = $2)",
+        &[list_id.into(), actor_id.into()],
+    )
+    .unwrap()
+    .unwrap_or(false);
+
+    if !owned {
+        pgrx::error!("list {} not found", list_id);
+    }
+}
+
+// =============================================================================
+// Hashtags
+// =============================================================================
+
+/// Get the public posts tagged with a hashtag, newest first. The tag may be
+/// given with or without its '#'. Pass the last id seen as max_id to page
+/// back.
+#[pg_extern]
+fn ap_tag_timeline(
+    tag: &str,
+    local_only: default!(bool, false),
+    max_results: default!(i32, 20),
+    max_id: default!(Option<i64>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(id, i64),
+        name!(uri, String),
+        name!(object_type, String),
+        name!(content, Option<String>),
+        name!(actor_uri, String),
+        name!(actor_username, String),
+        name!(published_at, Option<TimestampWithTimeZone>),
+    ),
+> {
+    let name = tag.trim_start_matches('#').to_lowercase();
+
+    let rows: Vec<_> = Spi::connect(|client| {
+        let mut results = Vec::new();
 
         let tup_table = client
             .select(
-                query,
+                "SELECT o.id, o.uri, o.object_type::text, o.content, a.uri, a.username,
+                        o.published_at
+                 FROM ap_hashtags h
+                 JOIN ap_object_hashtags oh ON oh.hashtag_id = h.id
+                 JOIN ap_objects o ON o.id = oh.object_id
+                 JOIN ap_actors a ON a.id = o.actor_id
+                 WHERE h.name = $1
+                 AND o.visibility = 'Public'
+                 AND (NOT $2 OR a.domain IS NULL)
+                 AND ($4::bigint IS NULL OR o.id < $4)
+                 AND ap_can_view(o.id, NULL)
+                 ORDER BY o.id DESC
+                 LIMIT $3",
                 None,
-                &[username.into(), max_results.into(), before_id.into()],
+                &[
+                    name.into(),
+                    local_only.into(),
+                    max_results.into(),
+                    max_id.into(),
+                ],
             )
-            .expect("failed to query home timeline");
+            .expect("failed to query tag timeline");
 
         for row in tup_table {
             let id: i64 = row
@@ -271,6 +680,215 @@ fn ap_home_timeline(
     TableIterator::new(rows)
 }
 
+/// Get the hashtags trending in public posts over the last `days` days,
+/// ranked by how many distinct accounts used them, then by number of uses.
+/// `history` holds the per-day uses and accounts, newest day first.
+#[pg_extern]
+fn ap_trending_tags(
+    max_results: default!(i32, 10),
+    days: default!(i32, 7),
+) -> TableIterator<
+    'static,
+    (
+        name!(name, String),
+        name!(uses, i64),
+        name!(accounts, i64),
+        name!(history, pgrx::JsonB),
+    ),
+> {
+    let rows: Vec<_> = Spi::connect(|client| {
+        let mut results = Vec::new();
+
+        let tup_table = client
+            .select(
+                "WITH uses AS (
+                    SELECT oh.hashtag_id, o.actor_id,
+                           date_trunc('day', o.published_at)::date AS day
+                    FROM ap_objects o
+                    JOIN ap_object_hashtags oh ON oh.object_id = o.id
+                    WHERE o.visibility = 'Public'
+                    AND o.published_at >= now() - make_interval(days => $2)
+                    AND ap_can_view(o.id, NULL)
+                 ),
+                 daily AS (
+                    SELECT hashtag_id, day, count(*) AS uses,
+                           count(DISTINCT actor_id) AS accounts
+                    FROM uses GROUP BY hashtag_id, day
+                 ),
+                 totals AS (
+                    SELECT hashtag_id, count(*) AS uses, count(DISTINCT actor_id) AS accounts
+                    FROM uses GROUP BY hashtag_id
+                 )
+                 SELECT h.name, t.uses, t.accounts,
+                    (SELECT jsonb_agg(jsonb_build_object(
+                                'day', d.day, 'uses', d.uses, 'accounts', d.accounts)
+                            ORDER BY d.day DESC)
+                     FROM daily d WHERE d.hashtag_id = t.hashtag_id)
+                 FROM totals t
+                 JOIN ap_hashtags h ON h.id = t.hashtag_id
+                 ORDER BY t.accounts DESC, t.uses DESC, h.name
+                 LIMIT $1",
+                None,
+                &[max_results.into(), days.into()],
+            )
+            .expect("failed to query trending tags");
+
+        for row in tup_table {
+            let name: String = row
+                .get_datum_by_ordinal(1)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let uses: i64 = row
+                .get_datum_by_ordinal(2)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let accounts: i64 = row
+                .get_datum_by_ordinal(3)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let history: pgrx::JsonB = row
+                .get_datum_by_ordinal(4)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+
+            results.push((name, uses, accounts, history));
+        }
+
+        results
+    });
+
+    TableIterator::new(rows)
+}
+
+// =============================================================================
+// Threads
+// =============================================================================
+
+/// Replies are followed at most this many levels up or down from the focus.
+const MAX_THREAD_DEPTH: i32 = 100;
+
+/// Get the thread around an object: its ancestors (negative depth, root first),
+/// the object itself (depth 0), then its descendants depth-first (positive
+/// depth). Only objects the viewer may see are returned; viewer NULL sees what
+/// an anonymous visitor would. Returns no rows if the object itself is hidden.
+#[pg_extern]
+fn ap_thread(
+    object_uri: &str,
+    viewer: default!(Option<&str>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(depth, i32),
+        name!(uri, String),
+        name!(object_type, String),
+        name!(content, Option<String>),
+        name!(actor_uri, String),
+        name!(in_reply_to_uri, Option<String>),
+        name!(published_at, Option<TimestampWithTimeZone>),
+    ),
+> {
+    let viewer_id = viewer.map(|username| local_actor(username).0);
+
+    let rows: Vec<_> = Spi::connect(|client| {
+        let mut results = Vec::new();
+
+        let tup_table = client
+            .select(
+                "WITH RECURSIVE focus AS (
+                    SELECT id, uri, in_reply_to_uri FROM ap_objects
+                    WHERE uri = $1 AND ap_can_view(id, $2)
+                 ),
+                 ancestors (id, in_reply_to_uri, depth) AS (
+                    SELECT p.id, p.in_reply_to_uri, -1
+                    FROM ap_objects p JOIN focus f ON p.uri = f.in_reply_to_uri
+                    UNION ALL
+                    SELECT p.id, p.in_reply_to_uri, a.depth - 1
+                    FROM ap_objects p JOIN ancestors a ON p.uri = a.in_reply_to_uri
+                    WHERE a.depth > -$3
+                 ),
+                 descendants (id, uri, depth, path) AS (
+                    SELECT c.id, c.uri, 1, ARRAY[c.id]
+                    FROM ap_objects c JOIN focus f ON c.in_reply_to_uri = f.uri
+                    UNION ALL
+                    SELECT c.id, c.uri, d.depth + 1, d.path || c.id
+                    FROM ap_objects c JOIN descendants d ON c.in_reply_to_uri = d.uri
+                    WHERE d.depth < $3 AND c.id <> ALL(d.path)
+                 ),
+                 thread (id, depth, path) AS (
+                    SELECT id, depth, ARRAY[]::bigint[] FROM ancestors
+                    UNION ALL
+                    SELECT id, 0, ARRAY[]::bigint[] FROM focus
+                    UNION ALL
+                    SELECT id, depth, path FROM descendants
+                 )
+                 SELECT t.depth, o.uri, o.object_type::text, o.content, a.uri,
+                        o.in_reply_to_uri, o.published_at
+                 FROM thread t
+                 JOIN ap_objects o ON o.id = t.id
+                 JOIN ap_actors a ON a.id = o.actor_id
+                 WHERE t.depth = 0 OR ap_can_view(o.id, $2)
+                 ORDER BY t.depth > 0, CASE WHEN t.depth <= 0 THEN t.depth END, t.path",
+                None,
+                &[object_uri.into(), viewer_id.into(), MAX_THREAD_DEPTH.into()],
+            )
+            .expect("failed to query thread");
+
+        for row in tup_table {
+            let depth: i32 = row
+                .get_datum_by_ordinal(1)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let uri: String = row
+                .get_datum_by_ordinal(2)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let object_type: String = row
+                .get_datum_by_ordinal(3)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let content: Option<String> = row.get_datum_by_ordinal(4).unwrap().value().unwrap();
+            let actor_uri: String = row
+                .get_datum_by_ordinal(5)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let in_reply_to_uri: Option<String> =
+                row.get_datum_by_ordinal(6).unwrap().value().unwrap();
+            let published_at: Option<TimestampWithTimeZone> =
+                row.get_datum_by_ordinal(7).unwrap().value().unwrap();
+
+            results.push((
+                depth,
+                uri,
+                object_type,
+                content,
+                actor_uri,
+                in_reply_to_uri,
+                published_at,
+            ));
+        }
+
+        results
+    });
+
+    TableIterator::new(rows)
+}
+
 // =============================================================================
 // Maintenance
 // =============================================================================
@@ -293,6 +911,76 @@ fn ap_cleanup_expired_deliveries(older_than_days: default!(i32, 30)) -> i64 {
     .unwrap_or(0)
 }
 
+/// Delete keys whose rotation grace period has ended.
+/// Returns the number of deleted keys.
+#[pg_extern]
+fn ap_cleanup_expired_keys() -> i64 {
+    Spi::get_one::<i64>(
+        "WITH deleted AS (
+            DELETE FROM ap_keys WHERE expires_at <= now()
+            RETURNING id
+         )
+         SELECT count(*) FROM deleted",
+    )
+    .unwrap()
+    .unwrap_or(0)
+}
+
+/// Encrypt the private keys still stored in plaintext with the configured
+/// master key, e.g. after setting one for the first time.
+/// Returns the number of keys encrypted.
+#[pg_extern]
+fn ap_encrypt_private_keys() -> i64 {
+    let key = master_key().unwrap_or_else(|| {
+        pgrx::error!("set pg_fedi.master_key or pg_fedi.master_key_file to encrypt private keys")
+    });
+
+    let plaintext: Vec<(i64, String)> = Spi::connect(|client| {
+        let mut results = Vec::new();
+        let tup_table = client
+            .select(
+                "SELECT id, private_key_pem FROM ap_keys WHERE private_key_pem IS NOT NULL",
+                None,
+                &[],
+            )
+            .expect("failed to query private keys");
+
+        for row in tup_table {
+            let id: i64 = row
+                .get_datum_by_ordinal(1)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let private_key_pem: String = row
+                .get_datum_by_ordinal(2)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+
+            if !is_encrypted_private_key(&private_key_pem) {
+                results.push((id, private_key_pem));
+            }
+        }
+
+        results
+    });
+
+    for (id, private_key_pem) in &plaintext {
+        Spi::run_with_args(
+            "UPDATE ap_keys SET private_key_pem = $2 WHERE id = $1",
+            &[
+                (*id).into(),
+                encrypt_private_key(private_key_pem, &key).into(),
+            ],
+        )
+        .expect("failed to encrypt private key");
+    }
+
+    plaintext.len() as i64
+}
+
 /// Recalculate all actor stats from source data.
 /// Useful if stats get out of sync due to manual data changes.
 #[pg_extern]
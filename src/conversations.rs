@@ -0,0 +1,263 @@
+use pgrx::prelude::*;
+
+use crate::actors::local_actor;
+
+/// The Direct posts a local actor (`$1`) took part in, keyed by conversation.
+/// Posts without a conversation URI form a conversation of their own.
+const DIRECT_MESSAGES_SQL: &str = "
+    SELECT o.id, coalesce(o.conversation_uri, o.uri) AS conversation, o.actor_id
+    FROM ap_objects o
+    WHERE o.visibility = 'Direct' AND o.deleted_at IS NULL
+    AND (
+        o.actor_id = $1
+        OR o.id IN (SELECT object_id FROM ap_object_recipients WHERE actor_id = $1)
+        OR o.id IN (SELECT object_id FROM ap_mentions WHERE actor_id = $1)
+    )
+    AND ap_can_view(o.id, $1)";
+
+// =============================================================================
+// Direct conversations
+// =============================================================================
+
+/// List a local actor's direct conversations, most recently active first.
+/// Each row carries the other participants, the latest message, and whether
+/// anyone else has written since the actor last read it. Pass the last
+/// `last_id` seen as max_id to page back.
+#[pg_extern]
+fn ap_conversations(
+    username: &str,
+    max_results: default!(i32, 20),
+    max_id: default!(Option<i64>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(conversation_uri, String),
+        name!(participants, Vec<String>),
+        name!(last_id, i64),
+        name!(last_uri, String),
+        name!(last_actor_uri, String),
+        name!(last_content, Option<String>),
+        name!(last_published_at, Option<TimestampWithTimeZone>),
+        name!(unread, bool),
+    ),
+> {
+    let (actor_id, actor_uri) = local_actor(username);
+
+    let query = format!(
+        "WITH messages AS ({}),
+         latest AS (
+            SELECT conversation, max(id) AS last_id FROM messages GROUP BY conversation
+         )
+         SELECT l.conversation,
+            coalesce((
+                SELECT array_agg(p.uri ORDER BY p.uri) FROM (
+                    SELECT a.uri FROM messages m
+                    JOIN ap_actors a ON a.id = m.actor_id
+                    WHERE m.conversation = l.conversation
+                    UNION
+                    SELECT r.actor_uri FROM messages m
+                    JOIN ap_object_recipients r ON r.object_id = m.id
+                    WHERE m.conversation = l.conversation
+                ) p WHERE p.uri <> $2
+            ), '{{}}'),
+            l.last_id, o.uri, a.uri, o.content, o.published_at,
+            EXISTS (
+                SELECT 1 FROM messages m
+                WHERE m.conversation = l.conversation AND m.actor_id <> $1
+                AND m.id > coalesce((
+                    SELECT last_read_id FROM ap_conversation_reads cr
+                    WHERE cr.actor_id = $1 AND cr.conversation_uri = l.conversation
+                ), 0)
+            )
+         FROM latest l
+         JOIN ap_objects o ON o.id = l.last_id
+         JOIN ap_actors a ON a.id = o.actor_id
+         WHERE ($4::bigint IS NULL OR l.last_id < $4)
+         ORDER BY l.last_id DESC
+         LIMIT $3",
+        DIRECT_MESSAGES_SQL
+    );
+
+    let rows: Vec<_> = Spi::connect(|client| {
+        let mut results = Vec::new();
+
+        let tup_table = client
+            .select(
+                &query,
+                None,
+                &[
+                    actor_id.into(),
+                    actor_uri.into(),
+                    max_results.into(),
+                    max_id.into(),
+                ],
+            )
+            .expect("failed to query conversations");
+
+        for row in tup_table {
+            let conversation_uri: String = row
+                .get_datum_by_ordinal(1)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let participants: Vec<String> = row
+                .get_datum_by_ordinal(2)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let last_id: i64 = row
+                .get_datum_by_ordinal(3)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let last_uri: String = row
+                .get_datum_by_ordinal(4)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let last_actor_uri: String = row
+                .get_datum_by_ordinal(5)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let last_content: Option<String> =
+                row.get_datum_by_ordinal(6).unwrap().value().unwrap();
+            let last_published_at: Option<TimestampWithTimeZone> =
+                row.get_datum_by_ordinal(7).unwrap().value().unwrap();
+            let unread: bool = row
+                .get_datum_by_ordinal(8)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+
+            results.push((
+                conversation_uri,
+                participants,
+                last_id,
+                last_uri,
+                last_actor_uri,
+                last_content,
+                last_published_at,
+                unread,
+            ));
+        }
+
+        results
+    });
+
+    TableIterator::new(rows)
+}
+
+/// List the messages of one of a local actor's direct conversations, newest
+/// first. Pass the last id seen as max_id to page back.
+#[pg_extern]
+fn ap_conversation_messages(
+    username: &str,
+    conversation_uri: &str,
+    max_results: default!(i32, 20),
+    max_id: default!(Option<i64>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(id, i64),
+        name!(uri, String),
+        name!(actor_uri, String),
+        name!(content, Option<String>),
+        name!(in_reply_to_uri, Option<String>),
+        name!(published_at, Option<TimestampWithTimeZone>),
+    ),
+> {
+    let (actor_id, _) = local_actor(username);
+
+    let query = format!(
+        "WITH messages AS ({})
+         SELECT o.id, o.uri, a.uri, o.content, o.in_reply_to_uri, o.published_at
+         FROM messages m
+         JOIN ap_objects o ON o.id = m.id
+         JOIN ap_actors a ON a.id = o.actor_id
+         WHERE m.conversation = $2
+         AND ($4::bigint IS NULL OR o.id < $4)
+         ORDER BY o.id DESC
+         LIMIT $3",
+        DIRECT_MESSAGES_SQL
+    );
+
+    let rows: Vec<_> = Spi::connect(|client| {
+        let mut results = Vec::new();
+
+        let tup_table = client
+            .select(
+                &query,
+                None,
+                &[
+                    actor_id.into(),
+                    conversation_uri.into(),
+                    max_results.into(),
+                    max_id.into(),
+                ],
+            )
+            .expect("failed to query conversation messages");
+
+        for row in tup_table {
+            let id: i64 = row
+                .get_datum_by_ordinal(1)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let uri: String = row
+                .get_datum_by_ordinal(2)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let actor_uri: String = row
+                .get_datum_by_ordinal(3)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let content: Option<String> = row.get_datum_by_ordinal(4).unwrap().value().unwrap();
+            let in_reply_to_uri: Option<String> =
+                row.get_datum_by_ordinal(5).unwrap().value().unwrap();
+            let published_at: Option<TimestampWithTimeZone> =
+                row.get_datum_by_ordinal(6).unwrap().value().unwrap();
+
+            results.push((id, uri, actor_uri, content, in_reply_to_uri, published_at));
+        }
+
+        results
+    });
+
+    TableIterator::new(rows)
+}
+
+/// Mark a local actor's direct conversation read up to its latest message.
+/// Returns false if the actor has no messages in that conversation.
+#[pg_extern]
+fn ap_mark_conversation_read(username: &str, conversation_uri: &str) -> bool {
+    let (actor_id, _) = local_actor(username);
+
+    let query = format!(
+        "WITH messages AS ({})
+         INSERT INTO ap_conversation_reads (actor_id, conversation_uri, last_read_id)
+         SELECT $1, $2, max(id) FROM messages WHERE conversation = $2
+         HAVING count(*) > 0
+         ON CONFLICT (actor_id, conversation_uri) DO UPDATE SET
+            last_read_id = GREATEST(ap_conversation_reads.last_read_id, EXCLUDED.last_read_id),
+            updated_at = now()
+         RETURNING true",
+        DIRECT_MESSAGES_SQL
+    );
+
+    Spi::get_one_with_args::<bool>(&query, &[actor_id.into(), conversation_uri.into()])
+        .ok()
+        .flatten()
+        .unwrap_or(false)
+}
@@ -1,14 +1,36 @@
+use std::collections::HashMap;
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use ed25519_dalek::{
+    Signature as Ed25519Signature, SigningKey as Ed25519SigningKey,
+    VerifyingKey as Ed25519VerifyingKey,
+};
 use pgrx::prelude::*;
 use pkcs8::{EncodePrivateKey, EncodePublicKey, LineEnding};
 use rand::rngs::OsRng;
+use rand::RngCore;
 use rsa::pkcs1v15::{SigningKey, VerifyingKey};
 use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
 use rsa::{RsaPrivateKey, RsaPublicKey};
 use sha2::{Digest, Sha256};
 use signature::{SignatureEncoding, Signer, Verifier};
 
+use crate::guc::master_key;
+use crate::types::ApKeyType;
+use crate::util::{normalize_headers, unix_now};
+
 const RSA_KEY_BITS: usize = 2048;
 
+/// Multicodec prefix of an Ed25519 public key (varint 0xed).
+const ED25519_MULTICODEC: [u8; 2] = [0xed, 0x01];
+
+/// Prefix marking a private key encrypted at rest with AES-256-GCM.
+const ENCRYPTED_KEY_PREFIX: &str = "aes256gcm:";
+
+/// AES-GCM nonce length in bytes.
+const NONCE_LEN: usize = 12;
+
 // =============================================================================
 // Keypair generation
 // =============================================================================
@@ -46,6 +68,38 @@ fn ap_generate_keypair() -> TableIterator<
     TableIterator::once((public_pem, private_pem))
 }
 
+/// Generate an Ed25519 keypair, returning (public_key_pem, private_key_pem).
+/// Internal function used by actor creation.
+pub fn generate_ed25519_keypair() -> (String, String) {
+    let signing_key = Ed25519SigningKey::generate(&mut OsRng);
+
+    let private_pem = signing_key
+        .to_pkcs8_pem(LineEnding::LF)
+        .expect("failed to encode private key to PEM")
+        .to_string();
+
+    let public_pem = signing_key
+        .verifying_key()
+        .to_public_key_pem(LineEnding::LF)
+        .expect("failed to encode public key to PEM");
+
+    (public_pem, private_pem)
+}
+
+/// SQL-callable Ed25519 keypair generation. Returns a single-row table with
+/// (public_key_pem TEXT, private_key_pem TEXT).
+#[pg_extern]
+fn ap_generate_ed25519_keypair() -> TableIterator<
+    'static,
+    (
+        name!(public_key_pem, String),
+        name!(private_key_pem, String),
+    ),
+> {
+    let (public_pem, private_pem) = generate_ed25519_keypair();
+    TableIterator::once((public_pem, private_pem))
+}
+
 // =============================================================================
 // HTTP Signature support (draft-cavage-http-signatures)
 // =============================================================================
@@ -53,7 +107,7 @@ fn ap_generate_keypair() -> TableIterator<
 /// Compute the SHA-256 digest of a request body.
 /// Returns the value for the Digest header: "SHA-256=<base64>".
 #[pg_extern]
-fn ap_digest(body: &str) -> String {
+pub fn ap_digest(body: &str) -> String {
     let hash = Sha256::digest(body.as_bytes());
     let encoded = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, hash);
     format!("SHA-256={}", encoded)
@@ -94,6 +148,82 @@ fn ap_rsa_verify(public_key_pem: &str, data: &str, signature_b64: &str) -> bool
     verifying_key.verify(data.as_bytes(), &sig).is_ok()
 }
 
+/// Sign data with Ed25519 using a private key in PKCS#8 PEM format.
+/// Returns the base64-encoded signature.
+#[pg_extern]
+fn ap_ed25519_sign(private_key_pem: &str, data: &str) -> String {
+    let sig = ed25519_sign_bytes(private_key_pem, data.as_bytes());
+    base64::Engine::encode(&base64::engine::general_purpose::STANDARD, sig)
+}
+
+/// Verify an Ed25519 signature against a public key in SPKI PEM format.
+/// Returns true if the signature is valid.
+#[pg_extern]
+fn ap_ed25519_verify(public_key_pem: &str, data: &str, signature_b64: &str) -> bool {
+    let public_key = match Ed25519VerifyingKey::from_public_key_pem(public_key_pem) {
+        Ok(k) => k,
+        Err(_) => return false,
+    };
+
+    let sig_bytes =
+        match base64::Engine::decode(&base64::engine::general_purpose::STANDARD, signature_b64) {
+            Ok(b) => b,
+            Err(_) => return false,
+        };
+
+    ed25519_verify_bytes(&public_key, data.as_bytes(), &sig_bytes)
+}
+
+/// Sign raw bytes with Ed25519 using a private key in PKCS#8 PEM format.
+pub fn ed25519_sign_bytes(private_key_pem: &str, data: &[u8]) -> [u8; 64] {
+    let signing_key = Ed25519SigningKey::from_pkcs8_pem(private_key_pem)
+        .expect("failed to parse private key PEM");
+    signing_key.sign(data).to_bytes()
+}
+
+/// Verify an Ed25519 signature over raw bytes.
+pub fn ed25519_verify_bytes(public_key: &Ed25519VerifyingKey, data: &[u8], sig: &[u8]) -> bool {
+    match Ed25519Signature::from_slice(sig) {
+        Ok(sig) => public_key.verify(data, &sig).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Sign data with a private key of either type, returning the key type and
+/// the base64-encoded signature.
+pub fn sign_with_key(private_key_pem: &str, data: &str) -> (ApKeyType, String) {
+    if Ed25519SigningKey::from_pkcs8_pem(private_key_pem).is_ok() {
+        (ApKeyType::Ed25519, ap_ed25519_sign(private_key_pem, data))
+    } else {
+        (ApKeyType::Rsa, ap_rsa_sign(private_key_pem, data))
+    }
+}
+
+/// Verify a signature with a public key of either type.
+pub fn verify_with_key(public_key_pem: &str, data: &str, signature_b64: &str) -> bool {
+    match public_key_type(public_key_pem) {
+        Some(ApKeyType::Ed25519) => ap_ed25519_verify(public_key_pem, data, signature_b64),
+        Some(ApKeyType::Rsa) => ap_rsa_verify(public_key_pem, data, signature_b64),
+        None => false,
+    }
+}
+
+/// Parse an Ed25519 public key in SPKI PEM format.
+pub fn ed25519_public_key(public_key_pem: &str) -> Option<Ed25519VerifyingKey> {
+    Ed25519VerifyingKey::from_public_key_pem(public_key_pem).ok()
+}
+
+/// The type of a public key in SPKI PEM format, if it is one we support.
+pub fn public_key_type(public_key_pem: &str) -> Option<ApKeyType> {
+    if Ed25519VerifyingKey::from_public_key_pem(public_key_pem).is_ok() {
+        Some(ApKeyType::Ed25519)
+    } else if RsaPublicKey::from_public_key_pem(public_key_pem).is_ok() {
+        Some(ApKeyType::Rsa)
+    } else {
+        None
+    }
+}
+
 /// Build a complete HTTP Signature header value for an outbound ActivityPub request.
 ///
 /// Implements draft-cavage-http-signatures with headers:
@@ -101,7 +231,7 @@ fn ap_rsa_verify(public_key_pem: &str, data: &str, signature_b64: &str) -> bool
 ///
 /// Returns the full `Signature` header value ready to set on the request.
 #[pg_extern]
-fn ap_build_signature_header(
+pub fn ap_build_signature_header(
     key_id: &str,
     private_key_pem: &str,
     method: &str,
@@ -129,17 +259,16 @@ fn ap_build_signature_header(
         digest
     );
 
-    // Sign it
-    let private_key =
-        RsaPrivateKey::from_pkcs8_pem(private_key_pem).expect("failed to parse private key PEM");
-    let signing_key = SigningKey::<Sha256>::new(private_key);
-    let sig = signing_key.sign(signing_string.as_bytes());
-    let sig_b64 =
-        base64::Engine::encode(&base64::engine::general_purpose::STANDARD, sig.to_bytes());
+    // Sign it; Ed25519 keys are announced with the opaque "hs2019"
+    let (key_type, sig_b64) = sign_with_key(private_key_pem, &signing_string);
+    let algorithm = match key_type {
+        ApKeyType::Rsa => "rsa-sha256",
+        ApKeyType::Ed25519 => "hs2019",
+    };
 
     format!(
-        "keyId=\"{}\",algorithm=\"rsa-sha256\",headers=\"(request-target) host date digest\",signature=\"{}\"",
-        key_id, sig_b64
+        "keyId=\"{}\",algorithm=\"{}\",headers=\"(request-target) host date digest\",signature=\"{}\"",
+        key_id, algorithm, sig_b64
     )
 }
 
@@ -157,7 +286,7 @@ fn ap_build_signature_header(
 /// - digest: the Digest header value (optional, only needed if "digest" is in signed headers)
 /// - public_key_pem: the actor's public key in SPKI PEM format
 #[pg_extern]
-fn ap_verify_http_signature(
+pub fn ap_verify_http_signature(
     signature_header: &str,
     method: &str,
     path: &str,
@@ -205,14 +334,464 @@ fn ap_verify_http_signature(
 
     let signing_string = parts.join("\n");
 
+    // The declared algorithm, if specific, must match the key
+    let key_type = public_key_type(public_key_pem);
+    match (fields.algorithm.as_deref(), key_type) {
+        (Some("rsa-sha256"), Some(ApKeyType::Rsa)) => {}
+        (Some("ed25519"), Some(ApKeyType::Ed25519)) => {}
+        (None | Some("hs2019"), Some(_)) => {}
+        _ => return false,
+    }
+
     // Verify
-    ap_rsa_verify(public_key_pem, &signing_string, sig_b64)
+    verify_with_key(public_key_pem, &signing_string, sig_b64)
+}
+
+// =============================================================================
+// HTTP Message Signatures (RFC 9421)
+// =============================================================================
+
+/// Signature label used for outbound RFC 9421 signatures.
+const MESSAGE_SIGNATURE_LABEL: &str = "sig1";
+
+/// RFC 9421 algorithm names for our key types.
+const RSA_V1_5_SHA256: &str = "rsa-v1_5-sha256";
+const ED25519: &str = "ed25519";
+
+/// Compute the SHA-256 Content-Digest of a request body (RFC 9530).
+/// Returns the header value: "sha-256=:<base64>:".
+#[pg_extern]
+fn ap_content_digest(body: &str) -> String {
+    let hash = Sha256::digest(body.as_bytes());
+    let encoded = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, hash);
+    format!("sha-256=:{}:", encoded)
+}
+
+/// Build RFC 9421 signature headers for an outbound ActivityPub request.
+///
+/// Covers `@method`, `@target-uri` and `content-digest`, signed with
+/// rsa-v1_5-sha256 or ed25519 depending on the key. `created` defaults to
+/// now (seconds since the epoch).
+///
+/// Returns the `Signature-Input`, `Signature` and `Content-Digest` header
+/// values to set on the request.
+#[pg_extern]
+fn ap_build_message_signature(
+    key_id: &str,
+    private_key_pem: &str,
+    method: &str,
+    url: &str,
+    body: &str,
+    created: default!(Option<i64>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(signature_input, String),
+        name!(signature, String),
+        name!(content_digest, String),
+    ),
+> {
+    TableIterator::once(build_message_signature(
+        key_id,
+        private_key_pem,
+        method,
+        url,
+        body,
+        created,
+    ))
+}
+
+/// Build RFC 9421 signature headers, returning (signature_input, signature,
+/// content_digest). See `ap_build_message_signature`.
+pub fn build_message_signature(
+    key_id: &str,
+    private_key_pem: &str,
+    method: &str,
+    url: &str,
+    body: &str,
+    created: Option<i64>,
+) -> (String, String, String) {
+    let content_digest = ap_content_digest(body);
+    let alg = if Ed25519SigningKey::from_pkcs8_pem(private_key_pem).is_ok() {
+        ED25519
+    } else {
+        RSA_V1_5_SHA256
+    };
+    let params = format!(
+        "(\"@method\" \"@target-uri\" \"content-digest\");created={};keyid=\"{}\";alg=\"{}\"",
+        created.unwrap_or_else(unix_now),
+        key_id,
+        alg
+    );
+
+    let signature_base = format!(
+        "\"@method\": {}\n\"@target-uri\": {}\n\"content-digest\": {}\n\"@signature-params\": {}",
+        method.to_uppercase(),
+        url,
+        content_digest,
+        params
+    );
+    let (_, sig_b64) = sign_with_key(private_key_pem, &signature_base);
+
+    (
+        format!("{}={}", MESSAGE_SIGNATURE_LABEL, params),
+        format!("{}=:{}:", MESSAGE_SIGNATURE_LABEL, sig_b64),
+        content_digest,
+    )
+}
+
+/// Verify an incoming RFC 9421 HTTP message signature.
+///
+/// Rebuilds the signature base for the first signature declared in
+/// Signature-Input that has a value in Signature, and verifies it against
+/// the public key. Timestamps are not checked here.
+///
+/// Parameters:
+/// - signature_input: the raw `Signature-Input` header value
+/// - signature: the raw `Signature` header value
+/// - method: HTTP method (e.g., "POST")
+/// - target_uri: the full request URI (e.g., "https://example.com/inbox")
+/// - headers: a JSON object of the request headers covered by the signature
+/// - public_key_pem: the signer's public key in SPKI PEM format
+#[pg_extern]
+fn ap_verify_message_signature(
+    signature_input: &str,
+    signature: &str,
+    method: &str,
+    target_uri: &str,
+    headers: pgrx::Json,
+    public_key_pem: &str,
+) -> bool {
+    let headers = normalize_headers(&headers.0);
+
+    match select_message_signature(signature_input, signature) {
+        Some((input, sig_b64)) => verify_message_signature(
+            &input,
+            &sig_b64,
+            method,
+            target_uri,
+            &headers,
+            public_key_pem,
+        ),
+        None => false,
+    }
+}
+
+/// One signature declared in a Signature-Input header.
+pub struct SignatureInput {
+    pub label: String,
+    /// Covered components as serialized, e.g. `"@method"`.
+    pub components: Vec<String>,
+    pub key_id: Option<String>,
+    pub alg: Option<String>,
+    pub created: Option<i64>,
+    pub expires: Option<i64>,
+    /// The inner list as received, which is the `@signature-params` value.
+    pub params: String,
+}
+
+impl SignatureInput {
+    /// Whether the signature covers a component, e.g. `@method`.
+    pub fn covers(&self, component: &str) -> bool {
+        self.components
+            .iter()
+            .any(|c| c.trim_matches('"') == component)
+    }
+}
+
+/// Pick the signature to verify: the first one declared in Signature-Input
+/// that has a value in Signature. Returns it with its base64 signature.
+pub fn select_message_signature(
+    signature_input: &str,
+    signature: &str,
+) -> Option<(SignatureInput, String)> {
+    let values = parse_signature_dictionary(signature)?;
+    parse_signature_input(signature_input)?
+        .into_iter()
+        .find_map(|input| {
+            values
+                .iter()
+                .find(|(label, _)| *label == input.label)
+                .map(|(_, value)| value.clone())
+                .map(|value| (input, value))
+        })
+}
+
+/// Verify one RFC 9421 signature over a request. The signature base is
+/// rebuilt from the request; unsupported components or algorithms, or an
+/// algorithm that does not match the key, fail.
+pub fn verify_message_signature(
+    input: &SignatureInput,
+    sig_b64: &str,
+    method: &str,
+    target_uri: &str,
+    headers: &HashMap<String, String>,
+    public_key_pem: &str,
+) -> bool {
+    // The declared algorithm, if any, must match the key
+    let key_type = public_key_type(public_key_pem);
+    match (input.alg.as_deref(), key_type) {
+        (Some(RSA_V1_5_SHA256), Some(ApKeyType::Rsa)) => {}
+        (Some(ED25519), Some(ApKeyType::Ed25519)) => {}
+        (None, Some(_)) => {}
+        _ => return false,
+    }
+
+    let mut lines = Vec::new();
+    for component in &input.components {
+        match component_value(component, method, target_uri, headers) {
+            Some(value) => lines.push(format!("{}: {}", component, value)),
+            None => return false,
+        }
+    }
+    lines.push(format!("\"@signature-params\": {}", input.params));
+
+    verify_with_key(public_key_pem, &lines.join("\n"), sig_b64)
+}
+
+/// Resolve a covered component to its value in the signature base.
+/// Components with parameters (`;sf`, `;name=...`) are not supported.
+fn component_value(
+    component: &str,
+    method: &str,
+    target_uri: &str,
+    headers: &HashMap<String, String>,
+) -> Option<String> {
+    let name = component.strip_prefix('"')?.strip_suffix('"')?;
+    if name.contains('"') {
+        return None;
+    }
+
+    let (scheme, rest) = target_uri.split_once("://")?;
+    let (authority, request_target) = match rest.find('/') {
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+        None => (rest, "/"),
+    };
+    let (path, query) = match request_target.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (request_target, None),
+    };
+
+    match name {
+        "@method" => Some(method.to_uppercase()),
+        "@target-uri" => Some(target_uri.to_string()),
+        "@authority" => Some(authority.to_lowercase()),
+        "@scheme" => Some(scheme.to_lowercase()),
+        "@request-target" => Some(request_target.to_string()),
+        "@path" => Some(path.to_string()),
+        "@query" => Some(format!("?{}", query.unwrap_or(""))),
+        _ if name.starts_with('@') => None,
+        _ => headers.get(name).map(|v| v.trim().to_string()),
+    }
+}
+
+/// Check a Content-Digest header against a body. The header may list several
+/// algorithms; the sha-256 entry must be present and match.
+pub fn content_digest_matches(content_digest: &str, body: &str) -> bool {
+    let expected = ap_content_digest(body);
+    let expected_value = &expected["sha-256=".len()..];
+
+    split_members(content_digest)
+        .into_iter()
+        .any(|entry| match entry.split_once('=') {
+            Some((alg, value)) => alg.trim() == "sha-256" && value.trim() == expected_value,
+            None => false,
+        })
+}
+
+/// Parse a Signature-Input header (a structured field dictionary of inner
+/// lists) into the signatures it declares.
+pub fn parse_signature_input(header: &str) -> Option<Vec<SignatureInput>> {
+    let mut inputs = Vec::new();
+
+    for member in split_members(header) {
+        let (label, params) = member.split_once('=')?;
+        let params = params.trim();
+
+        let list = params.strip_prefix('(')?;
+        let close = list.find(')')?;
+        let components = split_outside_quotes(&list[..close], ' ')
+            .into_iter()
+            .filter(|c| !c.is_empty())
+            .map(|c| c.to_string())
+            .collect();
+
+        let mut input = SignatureInput {
+            label: label.trim().to_string(),
+            components,
+            key_id: None,
+            alg: None,
+            created: None,
+            expires: None,
+            params: params.to_string(),
+        };
+
+        for param in split_outside_quotes(&list[close + 1..], ';') {
+            let Some((key, value)) = param.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().trim_matches('"');
+            match key.trim() {
+                "keyid" => input.key_id = Some(value.to_string()),
+                "alg" => input.alg = Some(value.to_string()),
+                "created" => input.created = value.parse().ok(),
+                "expires" => input.expires = value.parse().ok(),
+                _ => {}
+            }
+        }
+
+        inputs.push(input);
+    }
+
+    Some(inputs)
+}
+
+/// Parse a Signature header (a structured field dictionary of byte
+/// sequences) into (label, base64 signature) pairs.
+pub fn parse_signature_dictionary(header: &str) -> Option<Vec<(String, String)>> {
+    split_members(header)
+        .into_iter()
+        .map(|member| {
+            let (label, value) = member.split_once('=')?;
+            let value = value.trim().strip_prefix(':')?.strip_suffix(':')?;
+            Some((label.trim().to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+// =============================================================================
+// Multikey (FEP-521a)
+// =============================================================================
+
+/// Encode an Ed25519 public key in SPKI PEM format as a Multikey
+/// `publicKeyMultibase` value (base58btc, "z6Mk..."). Returns NULL for
+/// other keys.
+#[pg_extern]
+pub fn ap_ed25519_multikey(public_key_pem: &str) -> Option<String> {
+    let public_key = Ed25519VerifyingKey::from_public_key_pem(public_key_pem).ok()?;
+
+    let mut bytes = ED25519_MULTICODEC.to_vec();
+    bytes.extend_from_slice(public_key.as_bytes());
+    Some(format!("z{}", bs58::encode(bytes).into_string()))
+}
+
+/// Decode a Multikey `publicKeyMultibase` value holding an Ed25519 public
+/// key into SPKI PEM format. Other key types are not supported.
+pub fn ed25519_pem_from_multikey(multibase: &str) -> Option<String> {
+    let bytes = bs58::decode(multibase.strip_prefix('z')?).into_vec().ok()?;
+    let key_bytes: [u8; 32] = bytes.strip_prefix(&ED25519_MULTICODEC)?.try_into().ok()?;
+
+    Ed25519VerifyingKey::from_bytes(&key_bytes)
+        .ok()?
+        .to_public_key_pem(LineEnding::LF)
+        .ok()
+}
+
+// =============================================================================
+// Private key encryption at rest
+// =============================================================================
+
+/// Prepare a private key for storage in `ap_keys`: encrypted under the
+/// master key if one is configured, as is otherwise.
+pub fn seal_private_key(private_key_pem: &str) -> String {
+    match master_key() {
+        Some(key) => encrypt_private_key(private_key_pem, &key),
+        None => private_key_pem.to_string(),
+    }
+}
+
+/// Recover a private key stored in `ap_keys` for signing. Encrypted keys need
+/// the master key they were sealed with.
+pub fn open_private_key(stored: &str) -> String {
+    if !is_encrypted_private_key(stored) {
+        return stored.to_string();
+    }
+
+    let key = master_key().unwrap_or_else(|| {
+        pgrx::error!("private key is encrypted but pg_fedi.master_key is not set")
+    });
+    decrypt_private_key(stored, &key)
+        .unwrap_or_else(|| pgrx::error!("failed to decrypt private key; wrong master key?"))
+}
+
+/// Whether a stored private key is encrypted.
+pub fn is_encrypted_private_key(stored: &str) -> bool {
+    stored.starts_with(ENCRYPTED_KEY_PREFIX)
+}
+
+/// Encrypt a private key with AES-256-GCM under a random nonce, returning
+/// "aes256gcm:" followed by the base64-encoded nonce and ciphertext.
+pub fn encrypt_private_key(private_key_pem: &str, master_key: &[u8; 32]) -> String {
+    let cipher = Aes256Gcm::new(master_key.into());
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), private_key_pem.as_bytes())
+        .expect("failed to encrypt private key");
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    format!(
+        "{}{}",
+        ENCRYPTED_KEY_PREFIX,
+        base64::Engine::encode(&base64::engine::general_purpose::STANDARD, sealed)
+    )
+}
+
+/// Decrypt a private key produced by `encrypt_private_key`. Returns None if
+/// the value is malformed or the master key does not match.
+pub fn decrypt_private_key(stored: &str, master_key: &[u8; 32]) -> Option<String> {
+    let encoded = stored.strip_prefix(ENCRYPTED_KEY_PREFIX)?;
+    let sealed =
+        base64::Engine::decode(&base64::engine::general_purpose::STANDARD, encoded).ok()?;
+    if sealed.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+
+    let cipher = Aes256Gcm::new(master_key.into());
+    let plaintext = cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+    String::from_utf8(plaintext).ok()
 }
 
 // =============================================================================
 // Helpers
 // =============================================================================
 
+/// Split a structured field dictionary into its members.
+fn split_members(header: &str) -> Vec<&str> {
+    split_outside_quotes(header, ',')
+        .into_iter()
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .collect()
+}
+
+/// Split on a separator that is not inside a quoted string or parentheses.
+fn split_outside_quotes(s: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut in_quotes = false;
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, ch) in s.char_indices() {
+        match ch {
+            '"' => in_quotes = !in_quotes,
+            '(' if !in_quotes => depth += 1,
+            ')' if !in_quotes => depth -= 1,
+            c if c == separator && !in_quotes && depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+
+    parts
+}
+
 /// Extract host and path from a URL string.
 fn parse_url_parts(url: &str) -> (String, String) {
     // Strip scheme
@@ -231,19 +810,32 @@ fn parse_url_parts(url: &str) -> (String, String) {
     }
 }
 
+/// Check a Digest header against a body. The header may list several
+/// algorithms; the SHA-256 entry must be present and match.
+pub fn digest_matches(digest_header: &str, body: &str) -> bool {
+    let expected = ap_digest(body);
+    let expected_value = &expected["SHA-256=".len()..];
+
+    digest_header.split(',').any(|entry| {
+        let entry = entry.trim();
+        match entry.split_once('=') {
+            Some((alg, value)) => alg.eq_ignore_ascii_case("SHA-256") && value == expected_value,
+            None => false,
+        }
+    })
+}
+
 /// Parsed fields from a Signature header.
-struct SignatureFields {
-    #[allow(dead_code)]
-    key_id: Option<String>,
-    #[allow(dead_code)]
-    algorithm: Option<String>,
-    headers: Option<String>,
-    signature: Option<String>,
+pub struct SignatureFields {
+    pub key_id: Option<String>,
+    pub algorithm: Option<String>,
+    pub headers: Option<String>,
+    pub signature: Option<String>,
 }
 
 /// Parse a Signature header value into its components.
 /// Format: `keyId="...",algorithm="...",headers="...",signature="..."`
-fn parse_signature_header(header: &str) -> Option<SignatureFields> {
+pub fn parse_signature_header(header: &str) -> Option<SignatureFields> {
     let mut key_id = None;
     let mut algorithm = None;
     let mut headers = None;
@@ -1,10 +1,40 @@
 use pgrx::prelude::*;
+use serde_json::json;
+
+use crate::crypto::{
+    ap_build_signature_header, ap_digest, build_message_signature, open_private_key,
+};
+use crate::types::ApSignatureScheme;
+use crate::util::{format_http_date, parse_domain, unix_now};
 
 /// Retry backoff schedule in seconds: 1m, 5m, 30m, 2h, 12h, 24h, 3d, 7d
 const RETRY_INTERVALS: &[i64] = &[60, 300, 1800, 7200, 43200, 86400, 259200, 604800];
 
+/// The signature scheme to knock with first, given the remembered row `s`:
+/// RFC 9421 unless the server fell back to draft-cavage in the last 30 days.
+const SIGNATURE_SCHEME_SQL: &str = "CASE WHEN s.scheme = 'Cavage'
+    AND s.updated_at > now() - interval '30 days' THEN 'Cavage' ELSE 'Rfc9421' END";
+
+/// Joins a delivery (`d`), its activity (`act`), the sending actor (`a`), the
+/// actor's newest RSA key (`k`) and the remembered signature scheme (`s`).
+const DELIVERY_JOINS_SQL: &str = "
+    FROM ap_deliveries d
+    JOIN ap_activities act ON act.id = d.activity_id
+    JOIN ap_actors a ON a.id = act.actor_id
+    JOIN LATERAL (
+        SELECT key_id, private_key_pem FROM ap_keys
+        WHERE actor_id = a.id AND key_type = 'Rsa' AND private_key_pem IS NOT NULL
+        AND expires_at IS NULL
+        ORDER BY id DESC LIMIT 1
+    ) k ON true
+    LEFT JOIN ap_signature_schemes s
+        ON s.domain = substring(d.inbox_uri from '^https?://([^/:]+)')";
+
 /// Get pending deliveries for the external worker.
-/// Returns rows with all info needed to perform the HTTP POST.
+/// Returns rows with all info needed to perform the HTTP POST: the request
+/// body, to be sent exactly as given, and the headers signing it with the
+/// actor's newest RSA key under the scheme to try first (see
+/// `ap_signature_rejected`). Private keys never leave the extension.
 #[pg_extern]
 fn ap_get_pending_deliveries(
     batch_size: i32,
@@ -16,26 +46,25 @@ fn ap_get_pending_deliveries(
         name!(activity_json, pgrx::JsonB),
         name!(actor_uri, String),
         name!(key_id, String),
-        name!(private_key_pem, String),
+        name!(signature_scheme, String),
+        name!(body, String),
+        name!(headers, pgrx::Json),
     ),
 > {
+    let query = format!(
+        "SELECT d.id, d.inbox_uri, act.raw, a.uri, k.key_id, k.private_key_pem, {}
+         {}
+         WHERE (d.status = 'Queued' OR d.status = 'Failed')
+           AND d.next_retry_at <= now()
+         ORDER BY d.next_retry_at
+         LIMIT $1",
+        SIGNATURE_SCHEME_SQL, DELIVERY_JOINS_SQL
+    );
+
     let rows: Vec<_> = Spi::connect(|client| {
         let mut results = Vec::new();
         let tup_table = client
-            .select(
-                "SELECT d.id, d.inbox_uri, act.raw, a.uri, k.key_id, k.private_key_pem
-                 FROM ap_deliveries d
-                 JOIN ap_activities act ON act.id = d.activity_id
-                 JOIN ap_actors a ON a.id = act.actor_id
-                 JOIN ap_keys k ON k.actor_id = a.id
-                 WHERE (d.status = 'Queued' OR d.status = 'Failed')
-                   AND d.next_retry_at <= now()
-                   AND k.private_key_pem IS NOT NULL
-                 ORDER BY d.next_retry_at
-                 LIMIT $1",
-                None,
-                &[batch_size.into()],
-            )
+            .select(&query, None, &[batch_size.into()])
             .expect("failed to query deliveries");
 
         for row in tup_table {
@@ -69,12 +98,25 @@ fn ap_get_pending_deliveries(
                 .value()
                 .unwrap()
                 .unwrap();
-            let private_key_pem: String = row
+            let private_key: String = row
                 .get_datum_by_ordinal(6)
                 .unwrap()
                 .value()
                 .unwrap()
                 .unwrap();
+            let signature_scheme: String = row
+                .get_datum_by_ordinal(7)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+
+            let body = activity_json.0.to_string();
+            let scheme = match signature_scheme.as_str() {
+                "Cavage" => ApSignatureScheme::Cavage,
+                _ => ApSignatureScheme::Rfc9421,
+            };
+            let headers = signature_headers(scheme, &key_id, &private_key, &inbox_uri, &body);
 
             results.push((
                 delivery_id,
@@ -82,7 +124,9 @@ fn ap_get_pending_deliveries(
                 activity_json,
                 actor_uri,
                 key_id,
-                private_key_pem,
+                signature_scheme,
+                body,
+                headers,
             ));
         }
 
@@ -92,6 +136,89 @@ fn ap_get_pending_deliveries(
     TableIterator::new(rows)
 }
 
+/// Sign a delivery again, e.g. to knock with the fallback scheme after a
+/// rejection. scheme defaults to the one remembered for the inbox's domain.
+/// Returns the request body and the headers to send it with.
+#[pg_extern]
+fn ap_sign_delivery(
+    delivery_id: i64,
+    scheme: default!(Option<ApSignatureScheme>, "NULL"),
+) -> TableIterator<'static, (name!(body, String), name!(headers, pgrx::Json))> {
+    let query = format!(
+        "SELECT d.inbox_uri, act.raw, k.key_id, k.private_key_pem, {}::ApSignatureScheme
+         {}
+         WHERE d.id = $1",
+        SIGNATURE_SCHEME_SQL, DELIVERY_JOINS_SQL
+    );
+
+    let delivery = Spi::connect(|client| {
+        let mut tup_table = client
+            .select(&query, Some(1), &[delivery_id.into()])
+            .expect("failed to query delivery");
+        let row = tup_table.next()?;
+
+        let inbox_uri: String = row.get_datum_by_ordinal(1).unwrap().value().unwrap()?;
+        let raw: pgrx::JsonB = row.get_datum_by_ordinal(2).unwrap().value().unwrap()?;
+        let key_id: String = row.get_datum_by_ordinal(3).unwrap().value().unwrap()?;
+        let private_key: String = row.get_datum_by_ordinal(4).unwrap().value().unwrap()?;
+        let remembered: ApSignatureScheme =
+            row.get_datum_by_ordinal(5).unwrap().value().unwrap()?;
+
+        Some((inbox_uri, raw, key_id, private_key, remembered))
+    });
+    let (inbox_uri, raw, key_id, private_key, remembered) = delivery.unwrap_or_else(|| {
+        pgrx::error!(
+            "delivery {} not found or its actor has no signing key",
+            delivery_id
+        )
+    });
+
+    let body = raw.0.to_string();
+    let headers = signature_headers(
+        scheme.unwrap_or(remembered),
+        &key_id,
+        &private_key,
+        &inbox_uri,
+        &body,
+    );
+
+    TableIterator::once((body, headers))
+}
+
+/// Sign a POST of body to an inbox with a stored private key, returning the
+/// request headers that carry the signature.
+fn signature_headers(
+    scheme: ApSignatureScheme,
+    key_id: &str,
+    stored_private_key: &str,
+    inbox_uri: &str,
+    body: &str,
+) -> pgrx::Json {
+    let private_key_pem = open_private_key(stored_private_key);
+
+    pgrx::Json(match scheme {
+        ApSignatureScheme::Rfc9421 => {
+            let (signature_input, signature, content_digest) =
+                build_message_signature(key_id, &private_key_pem, "POST", inbox_uri, body, None);
+            json!({
+                "Content-Digest": content_digest,
+                "Signature-Input": signature_input,
+                "Signature": signature,
+            })
+        }
+        ApSignatureScheme::Cavage => {
+            let date = format_http_date(unix_now());
+            let signature =
+                ap_build_signature_header(key_id, &private_key_pem, "POST", inbox_uri, &date, body);
+            json!({
+                "Date": date,
+                "Digest": ap_digest(body),
+                "Signature": signature,
+            })
+        }
+    })
+}
+
 /// Mark a delivery as successfully delivered.
 #[pg_extern]
 fn ap_delivery_success(delivery_id: i64, status_code: i32) {
@@ -204,3 +331,126 @@ fn ap_delivery_stats() -> TableIterator<'static, (name!(status, String), name!(c
 
     TableIterator::new(rows)
 }
+
+/// Queue delivery of an activity to the inboxes of an actor's accepted remote
+/// followers, preferring shared inboxes so each server receives it once.
+/// Followers the actor has blocked, or whose domain is blocked, are skipped.
+pub fn queue_follower_deliveries(activity_id: i64, actor_id: i64) {
+    Spi::run_with_args(
+        "INSERT INTO ap_deliveries (activity_id, inbox_uri)
+         SELECT DISTINCT $1, COALESCE(a.shared_inbox_uri, a.inbox_uri)
+         FROM ap_follows f
+         JOIN ap_actors a ON a.id = f.follower_id
+         WHERE f.following_id = $2 AND f.accepted = true
+         AND a.domain IS NOT NULL
+         AND NOT EXISTS (
+            SELECT 1 FROM ap_blocks b
+            WHERE (b.actor_id = $2 AND b.blocked_actor_id = a.id)
+               OR (b.blocked_domain = a.domain AND (b.actor_id IS NULL OR b.actor_id = $2))
+         )
+         ON CONFLICT (activity_id, inbox_uri) DO NOTHING",
+        &[activity_id.into(), actor_id.into()],
+    )
+    .expect("failed to queue follower deliveries");
+}
+
+/// Queue delivery of an activity to the given actors. Local and unknown
+/// actors are skipped.
+pub fn queue_actor_deliveries(activity_id: i64, actor_uris: &[String]) {
+    if actor_uris.is_empty() {
+        return;
+    }
+
+    Spi::run_with_args(
+        "INSERT INTO ap_deliveries (activity_id, inbox_uri)
+         SELECT DISTINCT $1, COALESCE(a.shared_inbox_uri, a.inbox_uri)
+         FROM ap_actors a
+         WHERE a.uri = ANY($2) AND a.domain IS NOT NULL
+         ON CONFLICT (activity_id, inbox_uri) DO NOTHING",
+        &[activity_id.into(), actor_uris.to_vec().into()],
+    )
+    .expect("failed to queue actor deliveries");
+}
+
+/// Queue delivery of an activity to every inbox an earlier activity was
+/// queued for, so follow-ups (Update, Delete, Undo) reach the same audience.
+pub fn queue_same_inboxes(activity_id: i64, original_activity_id: i64) {
+    Spi::run_with_args(
+        "INSERT INTO ap_deliveries (activity_id, inbox_uri)
+         SELECT DISTINCT $1, inbox_uri FROM ap_deliveries WHERE activity_id = $2
+         ON CONFLICT (activity_id, inbox_uri) DO NOTHING",
+        &[activity_id.into(), original_activity_id.into()],
+    )
+    .expect("failed to queue deliveries");
+}
+
+// =============================================================================
+// Signature scheme negotiation
+// =============================================================================
+
+/// The HTTP signature scheme to sign a request to an inbox with first.
+/// Servers are assumed to accept RFC 9421 until they reject it.
+#[pg_extern]
+fn ap_signature_scheme(inbox_uri: &str) -> String {
+    let domain = parse_domain(inbox_uri).unwrap_or_default();
+
+    Spi::get_one_with_args::<String>(
+        &format!(
+            "SELECT {} FROM (SELECT 1) one
+             LEFT JOIN ap_signature_schemes s ON s.domain = $1",
+            SIGNATURE_SCHEME_SQL
+        ),
+        &[domain.into()],
+    )
+    .expect("failed to query signature scheme")
+    .unwrap_or_else(|| "Rfc9421".to_string())
+}
+
+/// Record that an inbox accepted a request signed with the given scheme.
+#[pg_extern]
+fn ap_signature_accepted(inbox_uri: &str, scheme: ApSignatureScheme) {
+    if let Some(domain) = parse_domain(inbox_uri) {
+        record_signature_scheme(&domain, scheme);
+    }
+}
+
+/// Record that an inbox rejected (401/403) a request signed with the given
+/// scheme, and return the scheme to knock again with, or NULL if none is
+/// left. A rejected RFC 9421 signature falls back to draft-cavage, which is
+/// remembered for the domain; a rejected draft-cavage signature forgets it.
+#[pg_extern]
+fn ap_signature_rejected(inbox_uri: &str, scheme: ApSignatureScheme) -> Option<ApSignatureScheme> {
+    let domain = parse_domain(inbox_uri)?;
+
+    match scheme {
+        ApSignatureScheme::Rfc9421 => {
+            Spi::run_with_args(
+                "INSERT INTO ap_signature_schemes (domain, scheme) VALUES ($1, 'Cavage')
+                 ON CONFLICT (domain) DO UPDATE SET scheme = 'Cavage', updated_at = now()",
+                &[domain.into()],
+            )
+            .expect("failed to record signature scheme");
+            Some(ApSignatureScheme::Cavage)
+        }
+        ApSignatureScheme::Cavage => {
+            Spi::run_with_args(
+                "DELETE FROM ap_signature_schemes WHERE domain = $1 AND scheme = 'Cavage'",
+                &[domain.into()],
+            )
+            .expect("failed to forget signature scheme");
+            None
+        }
+    }
+}
+
+/// Remember the signature scheme a domain accepts. The timestamp only moves
+/// when the scheme changes.
+pub fn record_signature_scheme(domain: &str, scheme: ApSignatureScheme) {
+    Spi::run_with_args(
+        "INSERT INTO ap_signature_schemes (domain, scheme) VALUES ($1, $2)
+         ON CONFLICT (domain) DO UPDATE SET scheme = EXCLUDED.scheme, updated_at = now()
+         WHERE ap_signature_schemes.scheme <> EXCLUDED.scheme",
+        &[domain.into(), scheme.into()],
+    )
+    .expect("failed to record signature scheme");
+}
@@ -14,6 +14,14 @@ pub static MAX_DELIVERY_ATTEMPTS: GucSetting<i32> = GucSetting::<i32>::new(8);
 
 pub static DELIVERY_TIMEOUT_SECONDS: GucSetting<i32> = GucSetting::<i32>::new(30);
 
+pub static SIGNATURE_MAX_SKEW_SECONDS: GucSetting<i32> = GucSetting::<i32>::new(3600);
+
+pub static SIGN_ACTIVITIES: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+pub static MASTER_KEY: GucSetting<Option<CString>> = GucSetting::<Option<CString>>::new(None);
+
+pub static MASTER_KEY_FILE: GucSetting<Option<CString>> = GucSetting::<Option<CString>>::new(None);
+
 pub static USER_AGENT: GucSetting<Option<CString>> =
     GucSetting::<Option<CString>>::new(Some(c"pg_fedi/0.1.0"));
 
@@ -69,6 +77,44 @@ pub fn register_gucs() {
         GucFlags::default(),
     );
 
+    GucRegistry::define_int_guc(
+        c"pg_fedi.signature_max_skew_seconds",
+        c"Maximum difference between a signed request's Date header and now.",
+        c"Signed inbox requests dated further in the past or future are rejected.",
+        &SIGNATURE_MAX_SKEW_SECONDS,
+        30,
+        86400,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_bool_guc(
+        c"pg_fedi.sign_activities",
+        c"Attach integrity proofs to outbound activities.",
+        c"When true, local activities carry an eddsa-jcs-2022 proof (FEP-8b32) signed with the actor's Ed25519 key.",
+        &SIGN_ACTIVITIES,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_string_guc(
+        c"pg_fedi.master_key",
+        c"Master key for encrypting private keys at rest (32 bytes, base64-encoded).",
+        c"When set, new private keys are stored encrypted with AES-256-GCM. Visible to superusers only.",
+        &MASTER_KEY,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
+    GucRegistry::define_string_guc(
+        c"pg_fedi.master_key_file",
+        c"File holding the master key for encrypting private keys at rest.",
+        c"Read when pg_fedi.master_key is not set. The file holds the base64-encoded 32-byte key.",
+        &MASTER_KEY_FILE,
+        GucContext::Suset,
+        GucFlags::SUPERUSER_ONLY,
+    );
+
     GucRegistry::define_string_guc(
         c"pg_fedi.user_agent",
         c"User-Agent header for outbound HTTP requests.",
@@ -91,6 +137,34 @@ pub fn get_domain() -> String {
         .to_string()
 }
 
+/// Returns the master key for encrypting private keys at rest, from
+/// pg_fedi.master_key or else the file named by pg_fedi.master_key_file.
+/// None if neither is set.
+pub fn master_key() -> Option<[u8; 32]> {
+    let setting = |guc: &GucSetting<Option<CString>>| {
+        guc.get()
+            .map(|value| value.to_string_lossy().trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+
+    let encoded = match setting(&MASTER_KEY) {
+        Some(key) => key,
+        None => {
+            let path = setting(&MASTER_KEY_FILE)?;
+            std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| pgrx::error!("could not read pg_fedi.master_key_file: {}", e))
+                .trim()
+                .to_string()
+        }
+    };
+
+    let key = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, encoded)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .unwrap_or_else(|| pgrx::error!("pg_fedi master key must be 32 bytes, base64-encoded"));
+    Some(key)
+}
+
 /// Returns the base URL for this instance (e.g. "https://example.com").
 pub fn base_url() -> String {
     let scheme = if USE_HTTPS.get() { "https" } else { "http" };
@@ -0,0 +1,298 @@
+use pgrx::prelude::*;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::crypto::{ed25519_sign_bytes, ed25519_verify_bytes};
+use crate::util::{format_iso8601, json_str, unix_now};
+
+/// JSON-LD context defining the Data Integrity proof vocabulary.
+pub const DATA_INTEGRITY_CONTEXT: &str = "https://w3id.org/security/data-integrity/v2";
+
+const PROOF_TYPE: &str = "DataIntegrityProof";
+const CRYPTOSUITE: &str = "eddsa-jcs-2022";
+const PROOF_PURPOSE: &str = "assertionMethod";
+
+// =============================================================================
+// JSON canonicalization (RFC 8785)
+// =============================================================================
+
+/// Serialize a JSON value in JCS canonical form: no whitespace, object
+/// members sorted by the UTF-16 code units of their names, and numbers
+/// formatted as ECMAScript would.
+pub fn canonicalize(value: &Value) -> String {
+    let mut out = String::new();
+    write_canonical(value, &mut out);
+    out
+}
+
+/// SQL-callable JCS canonicalization.
+#[pg_extern]
+fn ap_jcs(document: pgrx::Json) -> String {
+    canonicalize(&document.0)
+}
+
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Null | Value::Bool(_) | Value::String(_) => {
+            out.push_str(&serde_json::to_string(value).expect("failed to serialize JSON value"));
+        }
+        Value::Number(n) => match n.as_f64() {
+            Some(f) if !(n.is_i64() || n.is_u64()) => out.push_str(&format_number(f)),
+            _ => out.push_str(&n.to_string()),
+        },
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            let mut members: Vec<(&String, &Value)> = map.iter().collect();
+            members.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+
+            out.push('{');
+            for (i, (key, item)) in members.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(key).expect("failed to serialize JSON key"));
+                out.push(':');
+                write_canonical(item, out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+/// Format a double the way ECMAScript's Number.prototype.toString does
+/// (ECMA-262 Number::toString), which JCS mandates.
+fn format_number(f: f64) -> String {
+    if f == 0.0 {
+        return "0".to_string();
+    }
+
+    // Rust's `{:e}` gives the shortest round-tripping digits, e.g. "-1.25e-7"
+    let formatted = format!("{:e}", f.abs());
+    let (mantissa, exponent) = formatted
+        .split_once('e')
+        .expect("exponent formatting always contains 'e'");
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let k = digits.len() as i32;
+    let n = exponent.parse::<i32>().expect("invalid float exponent") + 1;
+
+    let body = if k <= n && n <= 21 {
+        format!("{}{}", digits, "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        format!("{}.{}", &digits[..n as usize], &digits[n as usize..])
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{}", "0".repeat(-n as usize), digits)
+    } else {
+        let sign = if n > 0 { "+" } else { "-" };
+        let fraction = if k > 1 {
+            format!(".{}", &digits[1..])
+        } else {
+            String::new()
+        };
+        format!("{}{}e{}{}", &digits[..1], fraction, sign, (n - 1).abs())
+    };
+
+    if f < 0.0 {
+        format!("-{}", body)
+    } else {
+        body
+    }
+}
+
+// =============================================================================
+// Object integrity proofs (FEP-8b32)
+// =============================================================================
+
+/// Sign a JSON-LD document with an eddsa-jcs-2022 Data Integrity proof and
+/// return the document with the proof attached. verification_method is the
+/// id of the Ed25519 key, as listed in the actor's `assertionMethod`.
+#[pg_extern]
+fn ap_create_proof(
+    document: pgrx::Json,
+    verification_method: &str,
+    private_key_pem: &str,
+) -> pgrx::Json {
+    pgrx::Json(attach_proof(
+        &document.0,
+        verification_method,
+        private_key_pem,
+    ))
+}
+
+/// Verify a document's eddsa-jcs-2022 proof against an Ed25519 public key in
+/// SPKI PEM format. Returns false if the document has no such proof.
+#[pg_extern]
+fn ap_verify_proof(document: pgrx::Json, public_key_pem: &str) -> bool {
+    let public_key = match crate::crypto::ed25519_public_key(public_key_pem) {
+        Some(key) => key,
+        None => return false,
+    };
+    proofs(&document.0)
+        .iter()
+        .any(|proof| verify_single_proof(&document.0, proof, &public_key).is_ok())
+}
+
+/// Return a copy of the document with an eddsa-jcs-2022 proof attached,
+/// replacing any existing proof.
+pub fn attach_proof(document: &Value, verification_method: &str, private_key_pem: &str) -> Value {
+    let mut unsecured = document.clone();
+    if let Some(obj) = unsecured.as_object_mut() {
+        obj.remove("proof");
+    }
+
+    let mut proof = json!({
+        "type": PROOF_TYPE,
+        "cryptosuite": CRYPTOSUITE,
+        "verificationMethod": verification_method,
+        "proofPurpose": PROOF_PURPOSE,
+        "created": format_iso8601(unix_now()),
+    });
+
+    let hash_data = proof_hash_data(&unsecured, &proof);
+    let signature = ed25519_sign_bytes(private_key_pem, &hash_data);
+    proof["proofValue"] = json!(format!("z{}", bs58::encode(signature).into_string()));
+
+    unsecured["proof"] = proof;
+    unsecured
+}
+
+/// Add the Data Integrity context to a document's `@context` if missing.
+pub fn add_integrity_context(document: &mut Value) {
+    let context = match document.get_mut("@context") {
+        Some(context) => context,
+        None => return,
+    };
+    match context {
+        Value::Array(entries) if !entries.iter().any(|e| e == DATA_INTEGRITY_CONTEXT) => {
+            entries.push(json!(DATA_INTEGRITY_CONTEXT));
+        }
+        Value::String(s) if s != DATA_INTEGRITY_CONTEXT => {
+            *context = json!([s.clone(), DATA_INTEGRITY_CONTEXT]);
+        }
+        _ => {}
+    }
+}
+
+/// Verify the eddsa-jcs-2022 proof on an inbound document. The proof's
+/// verification method is resolved against `ap_keys` (unexpired Ed25519 keys
+/// only). Returns Ok(None) if the document carries no proof, Ok(Some(owner
+/// URI)) if a proof verifies, and Err(reason) otherwise.
+pub fn verify_document_proof(document: &Value) -> Result<Option<String>, String> {
+    let proofs = proofs(document);
+    if proofs.is_empty() {
+        return Ok(None);
+    }
+
+    let mut reason = String::new();
+    for proof in proofs {
+        let method = match json_str(proof, "verificationMethod") {
+            Some(method) => method,
+            None => {
+                reason = "proof has no verificationMethod".to_string();
+                continue;
+            }
+        };
+
+        let key = Spi::get_two_with_args::<String, String>(
+            "SELECT k.public_key_pem, a.uri FROM ap_keys k
+             JOIN ap_actors a ON a.id = k.actor_id
+             WHERE k.key_id = $1 AND k.key_type = 'Ed25519'
+             AND (k.expires_at IS NULL OR k.expires_at > now())",
+            &[method.clone().into()],
+        );
+        let (public_key_pem, owner_uri) = match key {
+            Ok((Some(pem), Some(owner))) => (pem, owner),
+            _ => {
+                reason = format!("unknown verification method '{}'", method);
+                continue;
+            }
+        };
+
+        let public_key = match crate::crypto::ed25519_public_key(&public_key_pem) {
+            Some(key) => key,
+            None => {
+                reason = format!("verification method '{}' is not an Ed25519 key", method);
+                continue;
+            }
+        };
+
+        match verify_single_proof(document, proof, &public_key) {
+            Ok(()) => return Ok(Some(owner_uri)),
+            Err(e) => reason = e,
+        }
+    }
+
+    Err(reason)
+}
+
+/// The proofs attached to a document; `proof` may be an object or a set.
+fn proofs(document: &Value) -> Vec<&Value> {
+    match document.get("proof") {
+        Some(Value::Array(proofs)) => proofs.iter().collect(),
+        Some(proof @ Value::Object(_)) => vec![proof],
+        _ => Vec::new(),
+    }
+}
+
+/// Check one proof against the document it is attached to.
+fn verify_single_proof(
+    document: &Value,
+    proof: &Value,
+    public_key: &ed25519_dalek::VerifyingKey,
+) -> Result<(), String> {
+    if json_str(proof, "type").as_deref() != Some(PROOF_TYPE)
+        || json_str(proof, "cryptosuite").as_deref() != Some(CRYPTOSUITE)
+    {
+        return Err("unsupported proof type or cryptosuite".to_string());
+    }
+    if json_str(proof, "proofPurpose").as_deref() != Some(PROOF_PURPOSE) {
+        return Err("proof purpose is not assertionMethod".to_string());
+    }
+
+    let signature = json_str(proof, "proofValue")
+        .and_then(|value| bs58::decode(value.strip_prefix('z')?).into_vec().ok())
+        .ok_or_else(|| "malformed proofValue".to_string())?;
+
+    let mut unsecured = document.clone();
+    if let Some(obj) = unsecured.as_object_mut() {
+        obj.remove("proof");
+    }
+    let mut options = proof.clone();
+    if let Some(obj) = options.as_object_mut() {
+        obj.remove("proofValue");
+    }
+
+    let hash_data = proof_hash_data(&unsecured, &options);
+    if ed25519_verify_bytes(public_key, &hash_data, &signature) {
+        Ok(())
+    } else {
+        Err("proof verification failed".to_string())
+    }
+}
+
+/// The data an eddsa-jcs-2022 proof signs: SHA-256 of the canonical proof
+/// configuration (the proof options, under the document's `@context`)
+/// followed by SHA-256 of the canonical unsecured document.
+fn proof_hash_data(unsecured: &Value, options: &Value) -> Vec<u8> {
+    let mut config = options.clone();
+    match unsecured.get("@context") {
+        Some(context) => config["@context"] = context.clone(),
+        None => {
+            if let Some(obj) = config.as_object_mut() {
+                obj.remove("@context");
+            }
+        }
+    }
+
+    let mut hash_data = Sha256::digest(canonicalize(&config).as_bytes()).to_vec();
+    hash_data.extend_from_slice(&Sha256::digest(canonicalize(unsecured).as_bytes()));
+    hash_data
+}
@@ -0,0 +1,109 @@
+use pgrx::prelude::*;
+use serde_json::Value;
+
+use crate::util::json_str;
+
+// =============================================================================
+// Attachments
+// =============================================================================
+
+/// Replace an object's attachments with those in an ActivityStreams
+/// `attachment` value (a single object or an array). Used both for local
+/// notes, whose attachments are supplied in the same shape, and for inbound
+/// Creates/Updates. Entries without a URL are ignored.
+pub fn sync_attachments(object_id: i64, attachment: Option<&Value>) {
+    Spi::run_with_args(
+        "DELETE FROM ap_attachments WHERE object_id = $1",
+        &[object_id.into()],
+    )
+    .expect("failed to clear attachments");
+
+    let items: Vec<&Value> = match attachment {
+        Some(Value::Array(items)) => items.iter().collect(),
+        Some(item @ Value::Object(_)) => vec![item],
+        _ => Vec::new(),
+    };
+
+    for (position, item) in items.into_iter().enumerate() {
+        let (url, link_media_type) = attachment_url(item.get("url"));
+        let url = match url {
+            Some(url) => url,
+            None => continue,
+        };
+
+        let attachment_type = json_str(item, "type").unwrap_or_else(|| "Document".into());
+        let media_type = json_str(item, "mediaType").or(link_media_type);
+        let focal_point = item.get("focalPoint").and_then(|v| v.as_array());
+        let focal_x = focal_point.and_then(|p| p.first()).and_then(|v| v.as_f64());
+        let focal_y = focal_point.and_then(|p| p.get(1)).and_then(|v| v.as_f64());
+
+        Spi::run_with_args(
+            "INSERT INTO ap_attachments (object_id, position, attachment_type, media_type,
+                url, name, width, height, blurhash, focal_x, focal_y)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+            &[
+                object_id.into(),
+                (position as i32).into(),
+                attachment_type.into(),
+                media_type.into(),
+                url.into(),
+                json_str(item, "name").into(),
+                json_i32(item, "width").into(),
+                json_i32(item, "height").into(),
+                json_str(item, "blurhash").into(),
+                focal_x.into(),
+                focal_y.into(),
+            ],
+        )
+        .expect("failed to insert attachment");
+    }
+}
+
+/// Build an object's ActivityStreams `attachment` array.
+pub fn serialize_attachments(object_id: i64) -> Vec<Value> {
+    let attachments = Spi::get_one_with_args::<pgrx::Json>(
+        "SELECT coalesce(json_agg(json_strip_nulls(json_build_object(
+                'type', attachment_type,
+                'mediaType', media_type,
+                'url', url,
+                'name', name,
+                'width', width,
+                'height', height,
+                'blurhash', blurhash,
+                'focalPoint', CASE WHEN focal_x IS NOT NULL AND focal_y IS NOT NULL
+                                   THEN json_build_array(focal_x, focal_y) END
+            )) ORDER BY position), '[]'::json)
+         FROM ap_attachments WHERE object_id = $1",
+        &[object_id.into()],
+    )
+    .expect("failed to query attachments")
+    .expect("attachments query returned no rows");
+
+    match attachments.0 {
+        Value::Array(attachments) => attachments,
+        _ => Vec::new(),
+    }
+}
+
+// =============================================================================
+// Helpers
+// =============================================================================
+
+/// Resolve an attachment's `url`, which may be a bare URI, a Link, or an array
+/// of Links. Returns the URL and the Link's media type, if any.
+fn attachment_url(url: Option<&Value>) -> (Option<String>, Option<String>) {
+    match url {
+        Some(Value::String(s)) => (Some(s.clone()), None),
+        Some(link @ Value::Object(_)) => (json_str(link, "href"), json_str(link, "mediaType")),
+        Some(Value::Array(links)) => attachment_url(links.first()),
+        _ => (None, None),
+    }
+}
+
+/// Extract a non-negative integer dimension from a JSON object.
+fn json_i32(obj: &Value, key: &str) -> Option<i32> {
+    obj.get(key)
+        .and_then(|v| v.as_i64())
+        .and_then(|n| i32::try_from(n).ok())
+        .filter(|n| *n >= 0)
+}
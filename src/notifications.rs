@@ -0,0 +1,369 @@
+use pgrx::prelude::*;
+
+use crate::actors::local_actor;
+use crate::types::ApNotificationType;
+
+// =============================================================================
+// Recording
+// =============================================================================
+
+/// Notify a local actor of an interaction by another actor. Nothing is
+/// recorded for remote or self-interactions, repeats of an existing
+/// notification, senders the recipient has blocked or muted notifications
+/// from, or objects the recipient cannot see.
+pub fn notify(
+    recipient_id: i64,
+    kind: ApNotificationType,
+    from_actor_id: i64,
+    object_id: Option<i64>,
+) {
+    Spi::run_with_args(
+        "INSERT INTO ap_notifications (actor_id, notification_type, from_actor_id, object_id)
+         SELECT r.id, $2, $3, $4
+         FROM ap_actors r
+         JOIN ap_actors f ON f.id = $3
+         WHERE r.id = $1 AND r.domain IS NULL AND r.id <> f.id
+         AND ($4::bigint IS NULL OR ap_can_view($4, r.id))
+         AND NOT EXISTS (
+            SELECT 1 FROM ap_notifications n
+            WHERE n.actor_id = r.id AND n.notification_type = $2
+            AND n.from_actor_id = f.id AND n.object_id IS NOT DISTINCT FROM $4
+         )
+         AND NOT EXISTS (
+            SELECT 1 FROM ap_blocks b
+            WHERE (b.actor_id = r.id AND b.blocked_actor_id = f.id)
+               OR (b.blocked_domain = f.domain AND (b.actor_id IS NULL OR b.actor_id = r.id))
+         )
+         AND NOT EXISTS (
+            SELECT 1 FROM ap_mutes m
+            WHERE m.actor_id = r.id AND m.muted_actor_id = f.id AND m.notifications
+            AND (m.expires_at IS NULL OR m.expires_at > now())
+         )",
+        &[
+            recipient_id.into(),
+            kind.into(),
+            from_actor_id.into(),
+            object_id.into(),
+        ],
+    )
+    .expect("failed to record notification");
+}
+
+/// Notify the author of an object of an interaction with it.
+pub fn notify_author(kind: ApNotificationType, from_actor_id: i64, object_id: i64) {
+    let author_id = Spi::get_one_with_args::<i64>(
+        "SELECT actor_id FROM ap_objects WHERE id = $1",
+        &[object_id.into()],
+    )
+    .ok()
+    .flatten();
+
+    if let Some(author_id) = author_id {
+        notify(author_id, kind, from_actor_id, Some(object_id));
+    }
+}
+
+/// Notify the local actors a new post mentions, and the author of the post it
+/// replies to if they are not mentioned.
+pub fn notify_audience(object_id: i64, from_actor_id: i64) {
+    let mentioned: Vec<i64> = Spi::connect(|client| {
+        let mut results = Vec::new();
+        let tup_table = client
+            .select(
+                "SELECT DISTINCT actor_id FROM ap_mentions
+                 WHERE object_id = $1 AND actor_id IS NOT NULL",
+                None,
+                &[object_id.into()],
+            )
+            .expect("failed to query mentions");
+
+        for row in tup_table {
+            if let Some(id) = row
+                .get_datum_by_ordinal(1)
+                .unwrap()
+                .value::<i64>()
+                .ok()
+                .flatten()
+            {
+                results.push(id);
+            }
+        }
+
+        results
+    });
+
+    for recipient_id in &mentioned {
+        notify(
+            *recipient_id,
+            ApNotificationType::Mention,
+            from_actor_id,
+            Some(object_id),
+        );
+    }
+
+    let parent_author = Spi::get_one_with_args::<i64>(
+        "SELECT p.actor_id FROM ap_objects o
+         JOIN ap_objects p ON p.uri = o.in_reply_to_uri
+         WHERE o.id = $1",
+        &[object_id.into()],
+    )
+    .ok()
+    .flatten();
+
+    if let Some(author_id) = parent_author.filter(|id| !mentioned.contains(id)) {
+        notify(
+            author_id,
+            ApNotificationType::Reply,
+            from_actor_id,
+            Some(object_id),
+        );
+    }
+}
+
+/// Withdraw the notifications an undone interaction produced. A NULL
+/// recipient or object matches any.
+pub fn retract(
+    kind: ApNotificationType,
+    from_actor_id: i64,
+    recipient_id: Option<i64>,
+    object_id: Option<i64>,
+) {
+    Spi::run_with_args(
+        "DELETE FROM ap_notifications
+         WHERE notification_type = $1 AND from_actor_id = $2
+         AND ($3::bigint IS NULL OR actor_id = $3)
+         AND ($4::bigint IS NULL OR object_id = $4)",
+        &[
+            kind.into(),
+            from_actor_id.into(),
+            recipient_id.into(),
+            object_id.into(),
+        ],
+    )
+    .expect("failed to retract notification");
+}
+
+// =============================================================================
+// Queries
+// =============================================================================
+
+/// List a local actor's notifications, newest first. Pass the last id seen as
+/// before_id to page back. Notifications about deleted posts are skipped.
+#[pg_extern]
+fn ap_notifications(
+    username: &str,
+    max_results: default!(i32, 20),
+    before_id: default!(Option<i64>, "NULL"),
+) -> TableIterator<
+    'static,
+    (
+        name!(id, i64),
+        name!(notification_type, String),
+        name!(from_actor_uri, String),
+        name!(object_uri, Option<String>),
+        name!(read, bool),
+        name!(created_at, TimestampWithTimeZone),
+    ),
+> {
+    let (actor_id, _) = local_actor(username);
+
+    let rows: Vec<_> = Spi::connect(|client| {
+        let mut results = Vec::new();
+
+        let tup_table = client
+            .select(
+                "SELECT n.id, n.notification_type::text, f.uri, o.uri,
+                        n.read_at IS NOT NULL, n.created_at
+                 FROM ap_notifications n
+                 JOIN ap_actors f ON f.id = n.from_actor_id
+                 LEFT JOIN ap_objects o ON o.id = n.object_id
+                 WHERE n.actor_id = $1
+                 AND ($3::bigint IS NULL OR n.id < $3)
+                 AND (n.object_id IS NULL OR o.deleted_at IS NULL)
+                 ORDER BY n.id DESC
+                 LIMIT $2",
+                None,
+                &[actor_id.into(), max_results.into(), before_id.into()],
+            )
+            .expect("failed to query notifications");
+
+        for row in tup_table {
+            let id: i64 = row
+                .get_datum_by_ordinal(1)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let notification_type: String = row
+                .get_datum_by_ordinal(2)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let from_actor_uri: String = row
+                .get_datum_by_ordinal(3)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let object_uri: Option<String> = row.get_datum_by_ordinal(4).unwrap().value().unwrap();
+            let read: bool = row
+                .get_datum_by_ordinal(5)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let created_at: TimestampWithTimeZone = row
+                .get_datum_by_ordinal(6)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+
+            results.push((
+                id,
+                notification_type,
+                from_actor_uri,
+                object_uri,
+                read,
+                created_at,
+            ));
+        }
+
+        results
+    });
+
+    TableIterator::new(rows)
+}
+
+/// Summarize a local actor's notifications grouped by type and post (follows
+/// form one group per type), most recently active group first. Each group
+/// lists the interacting actors, newest first.
+#[pg_extern]
+fn ap_notification_groups(
+    username: &str,
+    max_results: default!(i32, 20),
+) -> TableIterator<
+    'static,
+    (
+        name!(notification_type, String),
+        name!(object_uri, Option<String>),
+        name!(notifications_count, i64),
+        name!(unread_count, i64),
+        name!(from_actor_uris, Vec<String>),
+        name!(latest_id, i64),
+        name!(latest_at, TimestampWithTimeZone),
+    ),
+> {
+    let (actor_id, _) = local_actor(username);
+
+    let rows: Vec<_> = Spi::connect(|client| {
+        let mut results = Vec::new();
+
+        let tup_table = client
+            .select(
+                "SELECT n.notification_type::text, o.uri, count(*),
+                        count(*) FILTER (WHERE n.read_at IS NULL),
+                        array_agg(f.uri ORDER BY n.id DESC),
+                        max(n.id), max(n.created_at)
+                 FROM ap_notifications n
+                 JOIN ap_actors f ON f.id = n.from_actor_id
+                 LEFT JOIN ap_objects o ON o.id = n.object_id
+                 WHERE n.actor_id = $1
+                 AND (n.object_id IS NULL OR o.deleted_at IS NULL)
+                 GROUP BY n.notification_type, o.uri
+                 ORDER BY max(n.id) DESC
+                 LIMIT $2",
+                None,
+                &[actor_id.into(), max_results.into()],
+            )
+            .expect("failed to query notification groups");
+
+        for row in tup_table {
+            let notification_type: String = row
+                .get_datum_by_ordinal(1)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let object_uri: Option<String> = row.get_datum_by_ordinal(2).unwrap().value().unwrap();
+            let notifications_count: i64 = row
+                .get_datum_by_ordinal(3)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let unread_count: i64 = row
+                .get_datum_by_ordinal(4)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let from_actor_uris: Vec<String> = row
+                .get_datum_by_ordinal(5)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let latest_id: i64 = row
+                .get_datum_by_ordinal(6)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let latest_at: TimestampWithTimeZone = row
+                .get_datum_by_ordinal(7)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+
+            results.push((
+                notification_type,
+                object_uri,
+                notifications_count,
+                unread_count,
+                from_actor_uris,
+                latest_id,
+                latest_at,
+            ));
+        }
+
+        results
+    });
+
+    TableIterator::new(rows)
+}
+
+/// Mark a local actor's notifications read, up to and including up_to_id
+/// (NULL marks all). Returns the number of notifications marked.
+#[pg_extern]
+fn ap_mark_notifications_read(username: &str, up_to_id: default!(Option<i64>, "NULL")) -> i64 {
+    let (actor_id, _) = local_actor(username);
+
+    Spi::get_one_with_args::<i64>(
+        "WITH marked AS (
+            UPDATE ap_notifications SET read_at = now()
+            WHERE actor_id = $1 AND read_at IS NULL
+            AND ($2::bigint IS NULL OR id <= $2)
+            RETURNING 1
+         )
+         SELECT count(*) FROM marked",
+        &[actor_id.into(), up_to_id.into()],
+    )
+    .expect("failed to mark notifications read")
+    .unwrap_or(0)
+}
+
+/// Count a local actor's unread notifications.
+#[pg_extern]
+fn ap_unread_notifications_count(username: &str) -> i64 {
+    let (actor_id, _) = local_actor(username);
+
+    Spi::get_one_with_args::<i64>(
+        "SELECT count(*) FROM ap_notifications WHERE actor_id = $1 AND read_at IS NULL",
+        &[actor_id.into()],
+    )
+    .expect("failed to count notifications")
+    .unwrap_or(0)
+}
@@ -0,0 +1,162 @@
+use pgrx::prelude::*;
+use serde_json::{json, Value};
+
+use crate::activities::{allocate_activity, store_local_activity};
+use crate::actors::local_actor;
+use crate::delivery::queue_follower_deliveries;
+use crate::util::{json_id, AS_PUBLIC};
+
+/// Maximum number of posts a local actor can pin (matches Mastodon).
+const MAX_PINS: i64 = 5;
+
+// =============================================================================
+// Pinning (featured collection)
+// =============================================================================
+
+/// Pin one of a local actor's Public or Unlisted posts to their featured
+/// collection and federate an Add targeting it to followers.
+/// Returns the Add's URI, or NULL if the post was already pinned.
+#[pg_extern]
+fn ap_pin(username: &str, object_uri: &str) -> Option<String> {
+    let (actor_id, actor_uri) = local_actor(username);
+
+    let object = Spi::get_two_with_args::<i64, bool>(
+        "SELECT id, visibility IN ('Public', 'Unlisted') FROM ap_objects
+         WHERE uri = $1 AND actor_id = $2 AND deleted_at IS NULL",
+        &[object_uri.into(), actor_id.into()],
+    );
+    let object_id = match object {
+        Ok((Some(id), Some(true))) => id,
+        Ok((Some(_), Some(false))) => pgrx::error!("only Public and Unlisted posts can be pinned"),
+        _ => pgrx::error!(
+            "note '{}' not found for local actor '{}'",
+            object_uri,
+            username
+        ),
+    };
+
+    let (pinned, already_pinned) = Spi::get_two_with_args::<i64, bool>(
+        "SELECT count(*), coalesce(bool_or(object_uri = $2), false)
+         FROM ap_pins WHERE actor_id = $1",
+        &[actor_id.into(), object_uri.into()],
+    )
+    .expect("failed to query pins");
+
+    if already_pinned.unwrap_or(false) {
+        return None;
+    }
+    if pinned.unwrap_or(0) >= MAX_PINS {
+        pgrx::error!("cannot pin more than {} posts", MAX_PINS);
+    }
+
+    Spi::run_with_args(
+        "INSERT INTO ap_pins (actor_id, object_uri, object_id) VALUES ($1, $2, $3)",
+        &[actor_id.into(), object_uri.into(), object_id.into()],
+    )
+    .expect("failed to insert pin");
+
+    Some(send_featured_activity(
+        "Add", actor_id, &actor_uri, object_uri,
+    ))
+}
+
+/// Unpin a post from a local actor's featured collection and federate a
+/// Remove targeting it to followers.
+/// Returns the Remove's URI, or NULL if the post was not pinned.
+#[pg_extern]
+fn ap_unpin(username: &str, object_uri: &str) -> Option<String> {
+    let (actor_id, actor_uri) = local_actor(username);
+
+    Spi::get_one_with_args::<i64>(
+        "DELETE FROM ap_pins WHERE actor_id = $1 AND object_uri = $2 RETURNING id",
+        &[actor_id.into(), object_uri.into()],
+    )
+    .ok()
+    .flatten()?;
+
+    Some(send_featured_activity(
+        "Remove", actor_id, &actor_uri, object_uri,
+    ))
+}
+
+/// Build, store and queue an Add/Remove of an object to the actor's featured
+/// collection. Returns the activity's URI.
+fn send_featured_activity(
+    activity_type: &str,
+    actor_id: i64,
+    actor_uri: &str,
+    object_uri: &str,
+) -> String {
+    let (activity_id, activity_uri) = allocate_activity(actor_uri);
+    let activity_json = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": activity_uri,
+        "type": activity_type,
+        "actor": actor_uri,
+        "to": [AS_PUBLIC],
+        "cc": [format!("{}/followers", actor_uri)],
+        "object": object_uri,
+        "target": format!("{}/collections/featured", actor_uri)
+    });
+
+    store_local_activity(activity_id, actor_id, &activity_json);
+    queue_follower_deliveries(activity_id, actor_id);
+
+    activity_uri
+}
+
+// =============================================================================
+// Inbox processing
+// =============================================================================
+
+/// Apply an inbound Add/Remove when it targets the sending actor's own
+/// featured collection. Activities on other collections are ignored.
+pub fn process_featured_change(actor_id: i64, add: bool, activity: &Value) {
+    let (object_uri, target_uri) = match (json_id(activity, "object"), json_id(activity, "target"))
+    {
+        (Some(object), Some(target)) => (object, target),
+        _ => return,
+    };
+
+    let featured_uri = Spi::get_one_with_args::<String>(
+        "SELECT featured_uri FROM ap_actors WHERE id = $1",
+        &[actor_id.into()],
+    )
+    .ok()
+    .flatten();
+
+    if featured_uri.as_deref() != Some(target_uri.as_str()) {
+        return;
+    }
+
+    if add {
+        // Pins of objects we have not seen yet are linked when they arrive;
+        // objects known to belong to someone else are refused
+        Spi::run_with_args(
+            "INSERT INTO ap_pins (actor_id, object_uri, object_id)
+             SELECT $1, $2, (SELECT id FROM ap_objects WHERE uri = $2)
+             WHERE NOT EXISTS (
+                SELECT 1 FROM ap_objects WHERE uri = $2 AND actor_id <> $1
+             )
+             ON CONFLICT (actor_id, object_uri) DO NOTHING",
+            &[actor_id.into(), object_uri.into()],
+        )
+        .expect("failed to record pin");
+    } else {
+        Spi::run_with_args(
+            "DELETE FROM ap_pins WHERE actor_id = $1 AND object_uri = $2",
+            &[actor_id.into(), object_uri.into()],
+        )
+        .expect("failed to remove pin");
+    }
+}
+
+/// Link pins recorded before their object arrived.
+pub fn link_pins(object_id: i64, actor_id: i64, object_uri: &str) {
+    Spi::run_with_args(
+        "UPDATE ap_pins SET object_id = $1
+         WHERE actor_id = $2 AND object_uri = $3 AND object_id IS NULL",
+        &[object_id.into(), actor_id.into(), object_uri.into()],
+    )
+    .expect("failed to link pins");
+}
@@ -0,0 +1,401 @@
+use pgrx::prelude::*;
+use serde_json::{json, Map, Value};
+
+use crate::activities::{allocate_activity, publish_post, store_local_activity, LocalPost};
+use crate::actors::local_actor;
+use crate::delivery::queue_actor_deliveries;
+use crate::relationships::is_blocked_between;
+use crate::types::ApVisibility;
+use crate::util::{json_id, json_str};
+
+/// Poll settings for a local Question being published.
+pub struct NewPoll {
+    pub options: Vec<String>,
+    pub multiple: bool,
+    pub ends_at: Option<TimestampWithTimeZone>,
+}
+
+// =============================================================================
+// Authoring
+// =============================================================================
+
+/// Create a poll (a Question) and federate it like `ap_create_note`.
+/// `options` are the choices in display order; `multiple` allows voting for
+/// more than one; `ends_at` closes the poll (NULL = never).
+/// Returns the Question's URI.
+#[pg_extern]
+fn ap_create_poll(
+    username: &str,
+    content: &str,
+    options: Vec<String>,
+    multiple: default!(bool, false),
+    ends_at: default!(Option<TimestampWithTimeZone>, "NULL"),
+    visibility: default!(ApVisibility, "'Public'"),
+) -> String {
+    let options: Vec<String> = options.iter().map(|o| o.trim().to_string()).collect();
+    if options.len() < 2 {
+        pgrx::error!("a poll needs at least two options");
+    }
+    if options.iter().any(|o| o.is_empty()) {
+        pgrx::error!("poll options cannot be empty");
+    }
+    if (1..options.len()).any(|i| options[..i].contains(&options[i])) {
+        pgrx::error!("poll options must be unique");
+    }
+
+    publish_post(&LocalPost {
+        username,
+        content,
+        summary: None,
+        in_reply_to: None,
+        visibility,
+        recipients: Vec::new(),
+        attachments: None,
+        poll: Some(NewPoll {
+            options,
+            multiple,
+            ends_at,
+        }),
+    })
+}
+
+/// Store the poll and its options for a newly inserted local Question.
+pub fn record_poll(object_id: i64, poll: &NewPoll) {
+    Spi::run_with_args(
+        "INSERT INTO ap_polls (object_id, multiple, ends_at) VALUES ($1, $2, $3)",
+        &[object_id.into(), poll.multiple.into(), poll.ends_at.into()],
+    )
+    .expect("failed to insert poll");
+
+    Spi::run_with_args(
+        "INSERT INTO ap_poll_options (object_id, position, name)
+         SELECT $1, t.ord - 1, t.name
+         FROM unnest($2::text[]) WITH ORDINALITY AS t(name, ord)",
+        &[object_id.into(), poll.options.clone().into()],
+    )
+    .expect("failed to insert poll options");
+}
+
+// =============================================================================
+// Voting
+// =============================================================================
+
+/// Vote in a poll as a local actor. Votes on remote polls are sent to the
+/// poll's author as Creates of Notes whose `name` is the chosen option.
+/// Errors unless the poll is visible to the actor and neither blocks the
+/// other.
+/// Returns the URIs of the votes cast, one per choice.
+#[pg_extern]
+fn ap_vote(username: &str, poll_uri: &str, choices: Vec<String>) -> Vec<String> {
+    let (actor_id, actor_uri) = local_actor(username);
+
+    let poll = Spi::connect(|client| {
+        let mut tup_table = client
+            .select(
+                "SELECT o.id, a.id, a.uri, ap_can_view(o.id, $2)
+                 FROM ap_polls p
+                 JOIN ap_objects o ON o.id = p.object_id
+                 JOIN ap_actors a ON a.id = o.actor_id
+                 WHERE o.uri = $1 AND o.deleted_at IS NULL",
+                Some(1),
+                &[poll_uri.into(), actor_id.into()],
+            )
+            .expect("failed to query poll");
+        let row = tup_table.next()?;
+
+        let object_id: i64 = row.get_datum_by_ordinal(1).unwrap().value().unwrap()?;
+        let author_id: i64 = row.get_datum_by_ordinal(2).unwrap().value().unwrap()?;
+        let author_uri: String = row.get_datum_by_ordinal(3).unwrap().value().unwrap()?;
+        let visible: bool = row.get_datum_by_ordinal(4).unwrap().value().unwrap()?;
+
+        Some((object_id, author_id, author_uri, visible))
+    });
+    let (object_id, author_id, author_uri, visible) =
+        poll.unwrap_or_else(|| pgrx::error!("poll '{}' not found", poll_uri));
+
+    if is_blocked_between(actor_id, author_id) {
+        pgrx::error!(
+            "cannot vote in '{}': one of the actors blocks the other",
+            poll_uri
+        );
+    }
+    if !visible {
+        pgrx::error!("poll '{}' not found", poll_uri);
+    }
+
+    if author_uri == actor_uri {
+        pgrx::error!("cannot vote in your own poll");
+    }
+
+    let (multiple, closed, voted) = Spi::get_three_with_args::<bool, bool, bool>(
+        "SELECT p.multiple,
+                p.ends_at IS NOT NULL AND p.ends_at <= now(),
+                EXISTS(SELECT 1 FROM ap_poll_votes v WHERE v.object_id = $1 AND v.actor_id = $2)
+         FROM ap_polls p WHERE p.object_id = $1",
+        &[object_id.into(), actor_id.into()],
+    )
+    .expect("failed to query poll");
+
+    if closed.unwrap_or(false) {
+        pgrx::error!("poll has ended");
+    }
+    if voted.unwrap_or(false) {
+        pgrx::error!("already voted in this poll");
+    }
+
+    let mut choices_seen: Vec<&String> = Vec::new();
+    for choice in &choices {
+        if !choices_seen.contains(&choice) {
+            choices_seen.push(choice);
+        }
+    }
+    if choices_seen.is_empty() {
+        pgrx::error!("at least one choice is required");
+    }
+    if !multiple.unwrap_or(false) && choices_seen.len() > 1 {
+        pgrx::error!("this poll accepts a single choice");
+    }
+
+    let mut vote_uris = Vec::new();
+    for choice in choices_seen {
+        let vote_id = record_vote(object_id, actor_id, choice, None)
+            .unwrap_or_else(|| pgrx::error!("'{}' is not an option of this poll", choice));
+        let vote_uri = format!("{}#votes/{}", actor_uri, vote_id);
+
+        Spi::run_with_args(
+            "UPDATE ap_poll_votes SET uri = $2 WHERE id = $1",
+            &[vote_id.into(), vote_uri.clone().into()],
+        )
+        .expect("failed to set vote URI");
+
+        let (activity_id, activity_uri) = allocate_activity(&actor_uri);
+        let create_json = json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": activity_uri,
+            "type": "Create",
+            "actor": actor_uri,
+            "to": [author_uri],
+            "object": {
+                "id": vote_uri,
+                "type": "Note",
+                "attributedTo": actor_uri,
+                "to": [author_uri],
+                "name": choice,
+                "inReplyTo": poll_uri
+            }
+        });
+
+        store_local_activity(activity_id, actor_id, &create_json);
+        queue_actor_deliveries(activity_id, &[author_uri.clone()]);
+
+        vote_uris.push(vote_uri);
+    }
+
+    vote_uris
+}
+
+/// Handle an inbound vote: a Note with a `name` and no `content`, attributed
+/// to the sending actor, replying to a live local Question. Returns false if
+/// the object is not such a vote, so it can be processed as a regular post.
+/// Invalid votes (closed poll, unknown option, second choice on a
+/// single-choice poll) are dropped.
+pub fn process_vote(actor_id: i64, object: &Value) -> bool {
+    if json_str(object, "type").as_deref() != Some("Note") || json_str(object, "content").is_some()
+    {
+        return false;
+    }
+    let (choice, poll_uri, author_uri) = match (
+        json_str(object, "name"),
+        json_str(object, "inReplyTo"),
+        json_id(object, "attributedTo"),
+    ) {
+        (Some(choice), Some(poll_uri), Some(author_uri)) => (choice, poll_uri, author_uri),
+        _ => return false,
+    };
+
+    let poll_id = Spi::get_one_with_args::<i64>(
+        "SELECT o.id FROM ap_polls p
+         JOIN ap_objects o ON o.id = p.object_id
+         JOIN ap_actors a ON a.id = o.actor_id
+         WHERE o.uri = $1 AND a.domain IS NULL AND o.deleted_at IS NULL
+         AND EXISTS(SELECT 1 FROM ap_actors v WHERE v.id = $2 AND v.uri = $3)",
+        &[poll_uri.into(), actor_id.into(), author_uri.into()],
+    )
+    .ok()
+    .flatten();
+
+    match poll_id {
+        Some(poll_id) => {
+            record_vote(poll_id, actor_id, &choice, json_str(object, "id"));
+            true
+        }
+        None => false,
+    }
+}
+
+/// Record one vote and bump the tallies. Returns the vote's id, or None if
+/// the vote was not accepted.
+fn record_vote(object_id: i64, actor_id: i64, choice: &str, uri: Option<String>) -> Option<i64> {
+    let first_vote = !Spi::get_one_with_args::<bool>(
+        "SELECT EXISTS(SELECT 1 FROM ap_poll_votes WHERE object_id = $1 AND actor_id = $2)",
+        &[object_id.into(), actor_id.into()],
+    )
+    .expect("failed to query votes")
+    .unwrap_or(false);
+
+    let vote_id = Spi::get_one_with_args::<i64>(
+        "INSERT INTO ap_poll_votes (object_id, option_id, actor_id, uri)
+         SELECT $1, o.id, $2, $4
+         FROM ap_poll_options o
+         JOIN ap_polls p ON p.object_id = o.object_id
+         WHERE o.object_id = $1 AND o.name = $3
+         AND (p.ends_at IS NULL OR p.ends_at > now())
+         AND (p.multiple OR $5)
+         ON CONFLICT DO NOTHING
+         RETURNING id",
+        &[
+            object_id.into(),
+            actor_id.into(),
+            choice.into(),
+            uri.into(),
+            first_vote.into(),
+        ],
+    )
+    .ok()
+    .flatten()?;
+
+    Spi::run_with_args(
+        "UPDATE ap_poll_options SET votes_count = votes_count + 1
+         WHERE id = (SELECT option_id FROM ap_poll_votes WHERE id = $1)",
+        &[vote_id.into()],
+    )
+    .expect("failed to update option tally");
+
+    if first_vote {
+        Spi::run_with_args(
+            "UPDATE ap_polls SET voters_count = voters_count + 1 WHERE object_id = $1",
+            &[object_id.into()],
+        )
+        .expect("failed to update voter count");
+    }
+
+    Some(vote_id)
+}
+
+// =============================================================================
+// Remote polls
+// =============================================================================
+
+/// Store or refresh a remote Question's options and tallies from its
+/// `oneOf`/`anyOf` array. Remote servers own the counts, so they overwrite
+/// ours on every Create/Update.
+pub fn sync_remote_poll(object_id: i64, question: &Value) {
+    let (options, multiple) = match (question.get("oneOf"), question.get("anyOf")) {
+        (Some(Value::Array(options)), _) => (options, false),
+        (_, Some(Value::Array(options))) => (options, true),
+        _ => return,
+    };
+
+    let ends_at = json_str(question, "endTime").or_else(|| json_str(question, "closed"));
+    let voters_count = question.get("votersCount").and_then(|v| v.as_i64());
+
+    Spi::run_with_args(
+        "INSERT INTO ap_polls (object_id, multiple, ends_at, voters_count)
+         VALUES ($1, $2, $3::timestamptz, coalesce($4, 0))
+         ON CONFLICT (object_id) DO UPDATE SET
+            multiple = EXCLUDED.multiple,
+            ends_at = EXCLUDED.ends_at,
+            voters_count = coalesce($4, ap_polls.voters_count)",
+        &[
+            object_id.into(),
+            multiple.into(),
+            ends_at.into(),
+            voters_count.into(),
+        ],
+    )
+    .expect("failed to upsert poll");
+
+    let mut names = Vec::new();
+    for (position, option) in options.iter().enumerate() {
+        let name = match json_str(option, "name") {
+            Some(name) if !names.contains(&name) => name,
+            _ => continue,
+        };
+        let votes = option
+            .get("replies")
+            .and_then(|r| r.get("totalItems"))
+            .and_then(|v| v.as_i64())
+            .unwrap_or(0);
+
+        Spi::run_with_args(
+            "INSERT INTO ap_poll_options (object_id, position, name, votes_count)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (object_id, name) DO UPDATE SET
+                position = EXCLUDED.position,
+                votes_count = EXCLUDED.votes_count",
+            &[
+                object_id.into(),
+                (position as i32).into(),
+                name.clone().into(),
+                votes.into(),
+            ],
+        )
+        .expect("failed to upsert poll option");
+
+        names.push(name);
+    }
+
+    // Options removed by an edit take their votes with them
+    Spi::run_with_args(
+        "DELETE FROM ap_poll_options WHERE object_id = $1 AND NOT (name = ANY($2))",
+        &[object_id.into(), names.into()],
+    )
+    .expect("failed to prune poll options");
+}
+
+// =============================================================================
+// Serialization
+// =============================================================================
+
+/// Build the poll properties of a Question: `oneOf`/`anyOf` with per-option
+/// `replies.totalItems`, `votersCount`, `endTime` and `closed`.
+pub fn serialize_poll(object_id: i64) -> Option<Map<String, Value>> {
+    let poll = Spi::get_one_with_args::<pgrx::Json>(
+        "SELECT json_build_object(
+            'multiple', p.multiple,
+            'ends_at', p.ends_at,
+            'closed', p.ends_at IS NOT NULL AND p.ends_at <= now(),
+            'voters_count', p.voters_count,
+            'options', coalesce((
+                SELECT json_agg(json_build_object(
+                    'type', 'Note',
+                    'name', o.name,
+                    'replies', json_build_object('type', 'Collection', 'totalItems', o.votes_count)
+                ) ORDER BY o.position)
+                FROM ap_poll_options o WHERE o.object_id = p.object_id
+            ), '[]'::json)
+        ) FROM ap_polls p WHERE p.object_id = $1",
+        &[object_id.into()],
+    )
+    .ok()
+    .flatten()?;
+
+    let p = &poll.0;
+    let mut props = Map::new();
+
+    let key = if p["multiple"].as_bool().unwrap_or(false) {
+        "anyOf"
+    } else {
+        "oneOf"
+    };
+    props.insert(key.into(), p["options"].clone());
+    props.insert("votersCount".into(), p["voters_count"].clone());
+    if !p["ends_at"].is_null() {
+        props.insert("endTime".into(), p["ends_at"].clone());
+    }
+    if p["closed"].as_bool().unwrap_or(false) {
+        props.insert("closed".into(), p["ends_at"].clone());
+    }
+
+    Some(props)
+}
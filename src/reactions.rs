@@ -0,0 +1,230 @@
+use pgrx::prelude::*;
+use serde_json::json;
+
+use crate::activities::{allocate_activity, store_local_activity, undo_local_activity};
+use crate::actors::local_actor;
+use crate::delivery::{queue_actor_deliveries, queue_follower_deliveries};
+use crate::notifications::{notify_author, retract};
+use crate::relationships::is_blocked_between;
+use crate::types::ApNotificationType;
+use crate::util::AS_PUBLIC;
+
+// =============================================================================
+// Likes
+// =============================================================================
+
+/// Like an object as a local actor, delivering the Like to the object's author.
+/// Liking an already-liked object returns the existing Like's URI.
+/// Returns the Like activity's URI.
+#[pg_extern]
+fn ap_like(username: &str, object_uri: &str) -> String {
+    let (actor_id, actor_uri) = local_actor(username);
+    let target = reaction_target(actor_id, object_uri);
+
+    let existing = Spi::get_one_with_args::<String>(
+        "SELECT uri FROM ap_likes WHERE actor_id = $1 AND object_id = $2",
+        &[actor_id.into(), target.object_id.into()],
+    )
+    .ok()
+    .flatten();
+
+    if let Some(uri) = existing {
+        return uri;
+    }
+
+    let (activity_id, activity_uri) = allocate_activity(&actor_uri);
+    let like_json = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": activity_uri,
+        "type": "Like",
+        "actor": actor_uri,
+        "object": object_uri
+    });
+
+    store_local_activity(activity_id, actor_id, &like_json);
+
+    Spi::run_with_args(
+        "INSERT INTO ap_likes (actor_id, object_id, uri)
+         VALUES ($1, $2, $3)
+         ON CONFLICT (actor_id, object_id) DO UPDATE SET uri = EXCLUDED.uri",
+        &[
+            actor_id.into(),
+            target.object_id.into(),
+            activity_uri.clone().into(),
+        ],
+    )
+    .expect("failed to insert like");
+
+    notify_author(ApNotificationType::Like, actor_id, target.object_id);
+    queue_actor_deliveries(activity_id, &[target.author_uri]);
+
+    activity_uri
+}
+
+/// Remove a local actor's Like, federating Undo{Like} to the same inboxes.
+/// This works even if the object has since been deleted or hidden, or its
+/// author blocked.
+/// Returns the Undo's URI, or NULL if the object was not liked.
+#[pg_extern]
+fn ap_unlike(username: &str, object_uri: &str) -> Option<String> {
+    let (actor_id, actor_uri) = local_actor(username);
+
+    let reaction = Spi::get_two_with_args::<i64, String>(
+        "DELETE FROM ap_likes l USING ap_objects o
+         WHERE l.object_id = o.id AND l.actor_id = $1 AND o.uri = $2
+         RETURNING l.object_id, l.uri",
+        &[actor_id.into(), object_uri.into()],
+    );
+    let (object_id, reaction_uri) = match reaction {
+        Ok((Some(object_id), Some(uri))) => (object_id, uri),
+        _ => return None,
+    };
+
+    retract(ApNotificationType::Like, actor_id, None, Some(object_id));
+    Some(undo_local_activity(actor_id, &actor_uri, &reaction_uri))
+}
+
+// =============================================================================
+// Announces (boosts)
+// =============================================================================
+
+/// Boost an object as a local actor. The Announce is public and delivered to
+/// the booster's followers and the object's author. Only Public and Unlisted
+/// objects can be boosted. Boosting an already-boosted object returns the
+/// existing Announce's URI.
+/// Returns the Announce activity's URI.
+#[pg_extern]
+fn ap_announce(username: &str, object_uri: &str) -> String {
+    let (actor_id, actor_uri) = local_actor(username);
+    let target = reaction_target(actor_id, object_uri);
+
+    if !target.boostable {
+        pgrx::error!("only Public and Unlisted objects can be boosted");
+    }
+
+    let existing = Spi::get_one_with_args::<String>(
+        "SELECT uri FROM ap_announces WHERE actor_id = $1 AND object_id = $2",
+        &[actor_id.into(), target.object_id.into()],
+    )
+    .ok()
+    .flatten();
+
+    if let Some(uri) = existing {
+        return uri;
+    }
+
+    let (activity_id, activity_uri) = allocate_activity(&actor_uri);
+    let announce_json = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": activity_uri,
+        "type": "Announce",
+        "actor": actor_uri,
+        "to": [AS_PUBLIC],
+        "cc": [target.author_uri, format!("{}/followers", actor_uri)],
+        "object": object_uri
+    });
+
+    store_local_activity(activity_id, actor_id, &announce_json);
+
+    Spi::run_with_args(
+        "INSERT INTO ap_announces (actor_id, object_id, uri)
+         VALUES ($1, $2, $3)
+         ON CONFLICT (actor_id, object_id) DO UPDATE SET uri = EXCLUDED.uri",
+        &[
+            actor_id.into(),
+            target.object_id.into(),
+            activity_uri.clone().into(),
+        ],
+    )
+    .expect("failed to insert announce");
+
+    notify_author(ApNotificationType::Announce, actor_id, target.object_id);
+    queue_follower_deliveries(activity_id, actor_id);
+    queue_actor_deliveries(activity_id, &[target.author_uri]);
+
+    activity_uri
+}
+
+/// Remove a local actor's boost, federating Undo{Announce} to the same inboxes.
+/// As with ap_unlike, the object need no longer be visible.
+/// Returns the Undo's URI, or NULL if the object was not boosted.
+#[pg_extern]
+fn ap_unannounce(username: &str, object_uri: &str) -> Option<String> {
+    let (actor_id, actor_uri) = local_actor(username);
+
+    let reaction = Spi::get_two_with_args::<i64, String>(
+        "DELETE FROM ap_announces s USING ap_objects o
+         WHERE s.object_id = o.id AND s.actor_id = $1 AND o.uri = $2
+         RETURNING s.object_id, s.uri",
+        &[actor_id.into(), object_uri.into()],
+    );
+    let (object_id, reaction_uri) = match reaction {
+        Ok((Some(object_id), Some(uri))) => (object_id, uri),
+        _ => return None,
+    };
+
+    retract(
+        ApNotificationType::Announce,
+        actor_id,
+        None,
+        Some(object_id),
+    );
+    Some(undo_local_activity(actor_id, &actor_uri, &reaction_uri))
+}
+
+// =============================================================================
+// Helpers
+// =============================================================================
+
+/// The object being reacted to.
+struct ReactionTarget {
+    object_id: i64,
+    author_uri: String,
+    boostable: bool,
+}
+
+/// Resolve a live object and its author as seen by a local actor. Errors if
+/// the object is unknown or not visible to the actor, or if either the actor
+/// or the author blocks the other.
+fn reaction_target(actor_id: i64, object_uri: &str) -> ReactionTarget {
+    let target = Spi::connect(|client| {
+        let mut tup_table = client
+            .select(
+                "SELECT o.id, a.id, a.uri, o.visibility IN ('Public', 'Unlisted'),
+                    ap_can_view(o.id, $2)
+                 FROM ap_objects o
+                 JOIN ap_actors a ON a.id = o.actor_id
+                 WHERE o.uri = $1 AND o.deleted_at IS NULL",
+                Some(1),
+                &[object_uri.into(), actor_id.into()],
+            )
+            .expect("failed to query reaction target");
+        let row = tup_table.next()?;
+
+        let object_id: i64 = row.get_datum_by_ordinal(1).unwrap().value().unwrap()?;
+        let author_id: i64 = row.get_datum_by_ordinal(2).unwrap().value().unwrap()?;
+        let author_uri: String = row.get_datum_by_ordinal(3).unwrap().value().unwrap()?;
+        let boostable: bool = row.get_datum_by_ordinal(4).unwrap().value().unwrap()?;
+        let visible: bool = row.get_datum_by_ordinal(5).unwrap().value().unwrap()?;
+
+        Some((object_id, author_id, author_uri, boostable, visible))
+    });
+    let (object_id, author_id, author_uri, boostable, visible) =
+        target.unwrap_or_else(|| pgrx::error!("object '{}' not found", object_uri));
+
+    if is_blocked_between(actor_id, author_id) {
+        pgrx::error!(
+            "cannot react to '{}': one of the actors blocks the other",
+            object_uri
+        );
+    }
+    if !visible {
+        pgrx::error!("object '{}' not found", object_uri);
+    }
+
+    ReactionTarget {
+        object_id,
+        author_uri,
+        boostable,
+    }
+}
@@ -0,0 +1,570 @@
+use pgrx::prelude::*;
+use serde_json::json;
+
+use crate::activities::{
+    allocate_activity, send_follow_response, store_local_activity, undo_local_activity,
+};
+use crate::actors::local_actor;
+use crate::delivery::queue_actor_deliveries;
+use crate::guc::AUTO_ACCEPT_FOLLOWS;
+use crate::notifications::{notify, retract};
+use crate::types::ApNotificationType;
+
+// =============================================================================
+// Outbound follows
+// =============================================================================
+
+/// Follow another actor from a local actor.
+///
+/// Remote targets get a pending `ap_follows` row (`accepted = false`) and a
+/// Follow delivered to their inbox; the row flips when their Accept arrives
+/// and is removed on Reject. Local targets are resolved immediately according
+/// to their follow policy. Following an actor that is already followed (or
+/// pending) returns the existing Follow's URI.
+/// Returns the Follow activity's URI.
+#[pg_extern]
+fn ap_follow(username: &str, target_uri: &str) -> String {
+    let (actor_id, actor_uri) = local_actor(username);
+    let (target_id, target_is_local, target_approves) = follow_target(target_uri);
+
+    if target_id == actor_id {
+        pgrx::error!("an actor cannot follow itself");
+    }
+    if is_blocked_between(actor_id, target_id) {
+        pgrx::error!(
+            "cannot follow '{}': one of the actors blocks the other",
+            target_uri
+        );
+    }
+
+    let existing = Spi::get_one_with_args::<String>(
+        "SELECT uri FROM ap_follows WHERE follower_id = $1 AND following_id = $2",
+        &[actor_id.into(), target_id.into()],
+    )
+    .ok()
+    .flatten();
+
+    if let Some(uri) = existing {
+        return uri;
+    }
+
+    let (activity_id, activity_uri) = allocate_activity(&actor_uri);
+    let follow_json = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": activity_uri,
+        "type": "Follow",
+        "actor": actor_uri,
+        "object": target_uri
+    });
+
+    store_local_activity(activity_id, actor_id, &follow_json);
+
+    // Local targets need no round trip; remote ones stay pending until Accept
+    let accepted = target_is_local && AUTO_ACCEPT_FOLLOWS.get() && !target_approves;
+
+    Spi::run_with_args(
+        "INSERT INTO ap_follows (follower_id, following_id, uri, accepted)
+         VALUES ($1, $2, $3, $4)
+         ON CONFLICT (follower_id, following_id) DO UPDATE SET uri = EXCLUDED.uri",
+        &[
+            actor_id.into(),
+            target_id.into(),
+            activity_uri.clone().into(),
+            accepted.into(),
+        ],
+    )
+    .expect("failed to insert follow");
+
+    if target_is_local {
+        let kind = if accepted {
+            ApNotificationType::Follow
+        } else {
+            ApNotificationType::FollowRequest
+        };
+        notify(target_id, kind, actor_id, None);
+    } else {
+        queue_actor_deliveries(activity_id, &[target_uri.to_string()]);
+    }
+
+    activity_uri
+}
+
+/// Stop following an actor, or withdraw a pending follow request.
+/// Removes the `ap_follows` row and, for remote targets, delivers an
+/// Undo{Follow}. Returns the Undo's URI, or NULL if nothing was sent.
+#[pg_extern]
+fn ap_unfollow(username: &str, target_uri: &str) -> Option<String> {
+    let (actor_id, actor_uri) = local_actor(username);
+    let (target_id, target_is_local, _) = follow_target(target_uri);
+
+    let follow_uri = Spi::get_one_with_args::<String>(
+        "DELETE FROM ap_follows WHERE follower_id = $1 AND following_id = $2
+         RETURNING coalesce(uri, '')",
+        &[actor_id.into(), target_id.into()],
+    )
+    .ok()
+    .flatten()?;
+
+    // Lists only hold followed actors
+    Spi::run_with_args(
+        "DELETE FROM ap_list_members m USING ap_lists l
+         WHERE m.list_id = l.id AND l.actor_id = $1 AND m.actor_id = $2",
+        &[actor_id.into(), target_id.into()],
+    )
+    .expect("failed to remove unfollowed actor from lists");
+
+    if target_is_local {
+        retract(ApNotificationType::Follow, actor_id, Some(target_id), None);
+        retract(
+            ApNotificationType::FollowRequest,
+            actor_id,
+            Some(target_id),
+            None,
+        );
+        return None;
+    }
+
+    let (activity_id, activity_uri) = allocate_activity(&actor_uri);
+    let mut follow = json!({
+        "type": "Follow",
+        "actor": actor_uri,
+        "object": target_uri
+    });
+    if !follow_uri.is_empty() {
+        follow["id"] = json!(follow_uri);
+    }
+
+    let undo_json = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": activity_uri,
+        "type": "Undo",
+        "actor": actor_uri,
+        "object": follow
+    });
+
+    store_local_activity(activity_id, actor_id, &undo_json);
+    queue_actor_deliveries(activity_id, &[target_uri.to_string()]);
+
+    Some(activity_uri)
+}
+
+// =============================================================================
+// Follow requests
+// =============================================================================
+
+/// List follow requests awaiting approval by a local actor, oldest first.
+#[pg_extern]
+fn ap_pending_follow_requests(
+    username: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(follower_uri, String),
+        name!(follow_uri, Option<String>),
+        name!(requested_at, TimestampWithTimeZone),
+    ),
+> {
+    let (actor_id, _) = local_actor(username);
+
+    let rows: Vec<_> = Spi::connect(|client| {
+        let mut results = Vec::new();
+        let tup_table = client
+            .select(
+                "SELECT a.uri, f.uri, f.created_at
+                 FROM ap_follows f
+                 JOIN ap_actors a ON a.id = f.follower_id
+                 WHERE f.following_id = $1 AND NOT f.accepted
+                 ORDER BY f.created_at, f.id",
+                None,
+                &[actor_id.into()],
+            )
+            .expect("failed to query follow requests");
+
+        for row in tup_table {
+            let follower_uri: String = row
+                .get_datum_by_ordinal(1)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let follow_uri: Option<String> = row.get_datum_by_ordinal(2).unwrap().value().unwrap();
+            let requested_at: TimestampWithTimeZone = row
+                .get_datum_by_ordinal(3)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            results.push((follower_uri, follow_uri, requested_at));
+        }
+
+        results
+    });
+
+    TableIterator::new(rows)
+}
+
+/// Approve a pending follow request. Remote followers are sent an Accept
+/// referencing their original Follow.
+/// Returns the Accept's URI, or NULL if the follower is local.
+#[pg_extern]
+fn ap_accept_follow_request(username: &str, follower_uri: &str) -> Option<String> {
+    let (actor_id, actor_uri) = local_actor(username);
+    let (follower_id, follower_is_local, _) = follow_target(follower_uri);
+
+    let follow_uri = Spi::get_one_with_args::<String>(
+        "UPDATE ap_follows SET accepted = true
+         WHERE follower_id = $1 AND following_id = $2 AND NOT accepted
+         RETURNING coalesce(uri, '')",
+        &[follower_id.into(), actor_id.into()],
+    )
+    .ok()
+    .flatten()
+    .unwrap_or_else(|| pgrx::error!("no pending follow request from '{}'", follower_uri));
+
+    // The request is now a follow
+    Spi::run_with_args(
+        "UPDATE ap_notifications SET notification_type = 'Follow'
+         WHERE actor_id = $1 AND from_actor_id = $2 AND notification_type = 'FollowRequest'",
+        &[actor_id.into(), follower_id.into()],
+    )
+    .expect("failed to update follow request notification");
+
+    if follower_is_local {
+        return None;
+    }
+
+    let follow = original_follow(&follow_uri, follower_uri, &actor_uri);
+    Some(send_follow_response(
+        "Accept",
+        actor_id,
+        follower_id,
+        &follow,
+    ))
+}
+
+/// Decline a pending follow request, removing it. Remote followers are sent
+/// a Reject referencing their original Follow.
+/// Returns the Reject's URI, or NULL if the follower is local.
+#[pg_extern]
+fn ap_reject_follow_request(username: &str, follower_uri: &str) -> Option<String> {
+    let (actor_id, actor_uri) = local_actor(username);
+    let (follower_id, follower_is_local, _) = follow_target(follower_uri);
+
+    let follow_uri = Spi::get_one_with_args::<String>(
+        "DELETE FROM ap_follows
+         WHERE follower_id = $1 AND following_id = $2 AND NOT accepted
+         RETURNING coalesce(uri, '')",
+        &[follower_id.into(), actor_id.into()],
+    )
+    .ok()
+    .flatten()
+    .unwrap_or_else(|| pgrx::error!("no pending follow request from '{}'", follower_uri));
+
+    retract(
+        ApNotificationType::FollowRequest,
+        follower_id,
+        Some(actor_id),
+        None,
+    );
+
+    if follower_is_local {
+        return None;
+    }
+
+    let follow = original_follow(&follow_uri, follower_uri, &actor_uri);
+    Some(send_follow_response(
+        "Reject",
+        actor_id,
+        follower_id,
+        &follow,
+    ))
+}
+
+// =============================================================================
+// Blocks
+// =============================================================================
+
+/// Block an actor from a local actor. Follows in both directions, list
+/// memberships and notifications from them are removed, and a Block is
+/// delivered to remote targets. Blocking an already-blocked actor returns the
+/// existing Block's URI.
+/// Returns the Block activity's URI.
+#[pg_extern]
+fn ap_block_actor(username: &str, target_uri: &str) -> String {
+    let (actor_id, actor_uri) = local_actor(username);
+    let (target_id, target_is_local, _) = follow_target(target_uri);
+
+    if target_id == actor_id {
+        pgrx::error!("an actor cannot block itself");
+    }
+
+    let existing = Spi::get_one_with_args::<String>(
+        "SELECT uri FROM ap_blocks WHERE actor_id = $1 AND blocked_actor_id = $2",
+        &[actor_id.into(), target_id.into()],
+    )
+    .ok()
+    .flatten();
+
+    if let Some(uri) = existing {
+        return uri;
+    }
+
+    let (activity_id, activity_uri) = allocate_activity(&actor_uri);
+    let block_json = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": activity_uri,
+        "type": "Block",
+        "actor": actor_uri,
+        "to": [target_uri],
+        "object": target_uri
+    });
+
+    store_local_activity(activity_id, actor_id, &block_json);
+
+    Spi::run_with_args(
+        "INSERT INTO ap_blocks (actor_id, blocked_actor_id, uri) VALUES ($1, $2, $3)",
+        &[
+            actor_id.into(),
+            target_id.into(),
+            activity_uri.clone().into(),
+        ],
+    )
+    .expect("failed to insert block");
+
+    Spi::run_with_args(
+        "DELETE FROM ap_follows
+         WHERE (follower_id = $1 AND following_id = $2)
+            OR (follower_id = $2 AND following_id = $1)",
+        &[actor_id.into(), target_id.into()],
+    )
+    .expect("failed to remove follows of blocked actor");
+    Spi::run_with_args(
+        "DELETE FROM ap_list_members m USING ap_lists l
+         WHERE m.list_id = l.id AND l.actor_id = $1 AND m.actor_id = $2",
+        &[actor_id.into(), target_id.into()],
+    )
+    .expect("failed to remove blocked actor from lists");
+    Spi::run_with_args(
+        "DELETE FROM ap_notifications WHERE actor_id = $1 AND from_actor_id = $2",
+        &[actor_id.into(), target_id.into()],
+    )
+    .expect("failed to remove notifications from blocked actor");
+
+    if !target_is_local {
+        queue_actor_deliveries(activity_id, &[target_uri.to_string()]);
+    }
+
+    activity_uri
+}
+
+/// Lift a local actor's block, federating Undo{Block} to the same inboxes as
+/// the Block. Follows removed by the block are not restored.
+/// Returns false if the actor was not blocked.
+#[pg_extern]
+fn ap_unblock_actor(username: &str, target_uri: &str) -> bool {
+    let (actor_id, actor_uri) = local_actor(username);
+    let (target_id, _, _) = follow_target(target_uri);
+
+    let block = Spi::get_two_with_args::<i64, String>(
+        "DELETE FROM ap_blocks WHERE actor_id = $1 AND blocked_actor_id = $2
+         RETURNING id, uri",
+        &[actor_id.into(), target_id.into()],
+    );
+    let block_uri = match block {
+        Ok((Some(_), block_uri)) => block_uri,
+        _ => return false,
+    };
+
+    // A block recorded without a Block activity has nothing to undo remotely
+    if let Some(block_uri) = block_uri {
+        undo_local_activity(actor_id, &actor_uri, &block_uri);
+    }
+    true
+}
+
+/// List the actors a local actor has blocked, most recent first.
+#[pg_extern]
+fn ap_blocked_actors(username: &str) -> Vec<String> {
+    let (actor_id, _) = local_actor(username);
+
+    Spi::get_one_with_args::<Vec<String>>(
+        "SELECT coalesce(array_agg(a.uri ORDER BY b.created_at DESC), '{}')
+         FROM ap_blocks b
+         JOIN ap_actors a ON a.id = b.blocked_actor_id
+         WHERE b.actor_id = $1",
+        &[actor_id.into()],
+    )
+    .expect("failed to query blocked actors")
+    .unwrap_or_default()
+}
+
+// =============================================================================
+// Mutes
+// =============================================================================
+
+/// Mute an actor for a local actor: their posts and boosts are hidden from
+/// timelines and, if `notifications`, they no longer notify. Nothing is
+/// federated. A NULL `expires_at` mutes indefinitely; muting again replaces
+/// the previous settings.
+#[pg_extern]
+fn ap_mute_actor(
+    username: &str,
+    target_uri: &str,
+    notifications: default!(bool, true),
+    expires_at: default!(Option<TimestampWithTimeZone>, "NULL"),
+) {
+    let (actor_id, _) = local_actor(username);
+    let (target_id, _, _) = follow_target(target_uri);
+
+    if target_id == actor_id {
+        pgrx::error!("an actor cannot mute itself");
+    }
+
+    Spi::run_with_args(
+        "INSERT INTO ap_mutes (actor_id, muted_actor_id, notifications, expires_at)
+         VALUES ($1, $2, $3, $4)
+         ON CONFLICT (actor_id, muted_actor_id) DO UPDATE SET
+            notifications = EXCLUDED.notifications,
+            expires_at = EXCLUDED.expires_at,
+            created_at = now()",
+        &[
+            actor_id.into(),
+            target_id.into(),
+            notifications.into(),
+            expires_at.into(),
+        ],
+    )
+    .expect("failed to insert mute");
+}
+
+/// Unmute an actor. Returns false if they were not muted.
+#[pg_extern]
+fn ap_unmute_actor(username: &str, target_uri: &str) -> bool {
+    let (actor_id, _) = local_actor(username);
+    let (target_id, _, _) = follow_target(target_uri);
+
+    Spi::get_one_with_args::<bool>(
+        "DELETE FROM ap_mutes WHERE actor_id = $1 AND muted_actor_id = $2 RETURNING true",
+        &[actor_id.into(), target_id.into()],
+    )
+    .ok()
+    .flatten()
+    .unwrap_or(false)
+}
+
+/// List the actors a local actor currently mutes.
+#[pg_extern]
+fn ap_muted_actors(
+    username: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(actor_uri, String),
+        name!(notifications, bool),
+        name!(expires_at, Option<TimestampWithTimeZone>),
+    ),
+> {
+    let (actor_id, _) = local_actor(username);
+
+    let rows: Vec<_> = Spi::connect(|client| {
+        let mut results = Vec::new();
+
+        let tup_table = client
+            .select(
+                "SELECT a.uri, m.notifications, m.expires_at
+                 FROM ap_mutes m
+                 JOIN ap_actors a ON a.id = m.muted_actor_id
+                 WHERE m.actor_id = $1
+                 AND (m.expires_at IS NULL OR m.expires_at > now())
+                 ORDER BY m.created_at DESC",
+                None,
+                &[actor_id.into()],
+            )
+            .expect("failed to query mutes");
+
+        for row in tup_table {
+            let actor_uri: String = row
+                .get_datum_by_ordinal(1)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let notifications: bool = row
+                .get_datum_by_ordinal(2)
+                .unwrap()
+                .value()
+                .unwrap()
+                .unwrap();
+            let expires_at: Option<TimestampWithTimeZone> =
+                row.get_datum_by_ordinal(3).unwrap().value().unwrap();
+
+            results.push((actor_uri, notifications, expires_at));
+        }
+
+        results
+    });
+
+    TableIterator::new(rows)
+}
+
+// =============================================================================
+// Helpers
+// =============================================================================
+
+/// The Follow a request was made with: the stored inbound activity when we
+/// have it, otherwise a minimal reconstruction.
+fn original_follow(follow_uri: &str, follower_uri: &str, followed_uri: &str) -> serde_json::Value {
+    let raw = Spi::get_one_with_args::<pgrx::JsonB>(
+        "SELECT raw FROM ap_activities WHERE uri = $1 AND activity_type = 'Follow'",
+        &[follow_uri.into()],
+    )
+    .ok()
+    .flatten();
+
+    if let Some(raw) = raw {
+        return raw.0;
+    }
+
+    let mut follow = json!({
+        "type": "Follow",
+        "actor": follower_uri,
+        "object": followed_uri
+    });
+    if !follow_uri.is_empty() {
+        follow["id"] = json!(follow_uri);
+    }
+    follow
+}
+
+/// Resolve a follow target, returning (id, is_local, manually_approves_followers).
+/// Remote actors must have been fetched with `ap_upsert_remote_actor` first so
+/// we know their inbox.
+fn follow_target(target_uri: &str) -> (i64, bool, bool) {
+    let target = Spi::get_three_with_args::<i64, bool, bool>(
+        "SELECT id, domain IS NULL, manually_approves_followers
+         FROM ap_actors WHERE uri = $1",
+        &[target_uri.into()],
+    );
+
+    match target {
+        Ok((Some(id), Some(is_local), Some(approves))) => (id, is_local, approves),
+        _ => pgrx::error!(
+            "actor '{}' not found; fetch it with ap_upsert_remote_actor first",
+            target_uri
+        ),
+    }
+}
+
+/// Whether either actor has blocked the other.
+pub fn is_blocked_between(actor_id: i64, other_id: i64) -> bool {
+    Spi::get_one_with_args::<bool>(
+        "SELECT EXISTS(
+            SELECT 1 FROM ap_blocks
+            WHERE (actor_id = $1 AND blocked_actor_id = $2)
+               OR (actor_id = $2 AND blocked_actor_id = $1)
+         )",
+        &[actor_id.into(), other_id.into()],
+    )
+    .unwrap()
+    .unwrap_or(false)
+}
@@ -45,17 +45,21 @@ CREATE INDEX idx_actors_updated_at ON ap_actors (updated_at);
 
 -- =========================================================================
 -- ap_keys: Cryptographic keys for actors (separate table for security).
+-- An actor may hold several: RSA for HTTP signatures, Ed25519 for
+-- Multikey assertion methods.
 -- =========================================================================
 CREATE TABLE ap_keys (
     id              BIGSERIAL PRIMARY KEY,
     actor_id        BIGINT NOT NULL REFERENCES ap_actors(id) ON DELETE CASCADE,
     key_id          TEXT UNIQUE NOT NULL,       -- e.g. https://example.com/users/alice#main-key
+    key_type        ApKeyType NOT NULL DEFAULT 'Rsa',
     public_key_pem  TEXT NOT NULL,
-    private_key_pem TEXT,                       -- NULL for remote actors
+    private_key_pem TEXT,                       -- NULL for remote actors; encrypted under the master key if set
+    expires_at      TIMESTAMPTZ,                -- set when rotated out; NULL = current
     created_at      TIMESTAMPTZ NOT NULL DEFAULT now()
 );
 
-CREATE UNIQUE INDEX idx_keys_actor_id ON ap_keys (actor_id);
+CREATE INDEX idx_keys_actor_id ON ap_keys (actor_id, key_type);
 
 -- =========================================================================
 -- ap_objects: All content objects (notes, articles, etc.)
@@ -72,6 +76,8 @@ CREATE TABLE ap_objects (
     summary         TEXT,                       -- CW / content warning
     url             TEXT,                       -- human-browsable URL
     visibility      ApVisibility NOT NULL DEFAULT 'Public',
+    to_uris         TEXT[],                     -- addressing as published
+    cc_uris         TEXT[],
     sensitive       BOOLEAN NOT NULL DEFAULT false,
     language        TEXT,                       -- ISO 639 code
     published_at    TIMESTAMPTZ,
@@ -90,6 +96,124 @@ CREATE INDEX idx_objects_published_at ON ap_objects (published_at DESC);
 CREATE INDEX idx_objects_not_deleted ON ap_objects (id) WHERE deleted_at IS NULL;
 CREATE INDEX idx_objects_content_search ON ap_objects USING gin (to_tsvector('simple', coalesce(content_text, '')));
 
+-- =========================================================================
+-- ap_object_recipients: Actors an object is explicitly addressed to
+-- (to/cc minus Public and collections). Drives Direct/mention filtering.
+-- =========================================================================
+CREATE TABLE ap_object_recipients (
+    object_id       BIGINT NOT NULL REFERENCES ap_objects(id) ON DELETE CASCADE,
+    actor_uri       TEXT NOT NULL,
+    actor_id        BIGINT REFERENCES ap_actors(id) ON DELETE CASCADE,  -- NULL = not yet known
+    PRIMARY KEY (object_id, actor_uri)
+);
+
+CREATE INDEX idx_object_recipients_actor ON ap_object_recipients (actor_id) WHERE actor_id IS NOT NULL;
+
+-- =========================================================================
+-- ap_mentions: Actors mentioned in an object's `tag` array.
+-- =========================================================================
+CREATE TABLE ap_mentions (
+    object_id       BIGINT NOT NULL REFERENCES ap_objects(id) ON DELETE CASCADE,
+    actor_uri       TEXT NOT NULL,
+    actor_id        BIGINT REFERENCES ap_actors(id) ON DELETE CASCADE,  -- NULL = not yet known
+    name            TEXT,                   -- '@user@domain' as written
+    PRIMARY KEY (object_id, actor_uri)
+);
+
+CREATE INDEX idx_mentions_actor ON ap_mentions (actor_id) WHERE actor_id IS NOT NULL;
+
+-- =========================================================================
+-- ap_hashtags: Normalized hashtag names (lowercase, without '#').
+-- =========================================================================
+CREATE TABLE ap_hashtags (
+    id              BIGSERIAL PRIMARY KEY,
+    name            TEXT NOT NULL UNIQUE,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE ap_object_hashtags (
+    object_id       BIGINT NOT NULL REFERENCES ap_objects(id) ON DELETE CASCADE,
+    hashtag_id      BIGINT NOT NULL REFERENCES ap_hashtags(id) ON DELETE CASCADE,
+    PRIMARY KEY (object_id, hashtag_id)
+);
+
+CREATE INDEX idx_object_hashtags_hashtag ON ap_object_hashtags (hashtag_id, object_id);
+
+-- =========================================================================
+-- ap_emojis: Custom emoji referenced by objects (`Emoji` tags).
+-- =========================================================================
+CREATE TABLE ap_emojis (
+    id              BIGSERIAL PRIMARY KEY,
+    shortcode       TEXT NOT NULL,          -- without surrounding colons
+    domain          TEXT NOT NULL,
+    uri             TEXT,
+    image_url       TEXT NOT NULL,
+    media_type      TEXT,
+    updated_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE(shortcode, domain)
+);
+
+CREATE TABLE ap_object_emojis (
+    object_id       BIGINT NOT NULL REFERENCES ap_objects(id) ON DELETE CASCADE,
+    emoji_id        BIGINT NOT NULL REFERENCES ap_emojis(id) ON DELETE CASCADE,
+    PRIMARY KEY (object_id, emoji_id)
+);
+
+CREATE INDEX idx_object_emojis_emoji ON ap_object_emojis (emoji_id);
+
+-- =========================================================================
+-- ap_attachments: Media attached to objects, in display order.
+-- =========================================================================
+CREATE TABLE ap_attachments (
+    id              BIGSERIAL PRIMARY KEY,
+    object_id       BIGINT NOT NULL REFERENCES ap_objects(id) ON DELETE CASCADE,
+    position        INT NOT NULL DEFAULT 0,
+    attachment_type TEXT NOT NULL DEFAULT 'Document',  -- Document, Image, Video, Audio
+    media_type      TEXT,
+    url             TEXT NOT NULL,
+    name            TEXT,                   -- alt text
+    width           INT,
+    height          INT,
+    blurhash        TEXT,
+    focal_x         DOUBLE PRECISION,       -- -1.0 (left) .. 1.0 (right)
+    focal_y         DOUBLE PRECISION,       -- -1.0 (bottom) .. 1.0 (top)
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_attachments_object ON ap_attachments (object_id, position);
+
+-- =========================================================================
+-- ap_polls: Question objects and their tallies. For remote polls the
+-- counts are copied from the origin server; for local ones we keep them.
+-- =========================================================================
+CREATE TABLE ap_polls (
+    object_id       BIGINT PRIMARY KEY REFERENCES ap_objects(id) ON DELETE CASCADE,
+    multiple        BOOLEAN NOT NULL DEFAULT false,   -- anyOf vs oneOf
+    ends_at         TIMESTAMPTZ,
+    voters_count    BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE TABLE ap_poll_options (
+    id              BIGSERIAL PRIMARY KEY,
+    object_id       BIGINT NOT NULL REFERENCES ap_polls(object_id) ON DELETE CASCADE,
+    position        INT NOT NULL,
+    name            TEXT NOT NULL,
+    votes_count     BIGINT NOT NULL DEFAULT 0,
+    UNIQUE(object_id, name)
+);
+
+CREATE TABLE ap_poll_votes (
+    id              BIGSERIAL PRIMARY KEY,
+    object_id       BIGINT NOT NULL REFERENCES ap_polls(object_id) ON DELETE CASCADE,
+    option_id       BIGINT NOT NULL REFERENCES ap_poll_options(id) ON DELETE CASCADE,
+    actor_id        BIGINT NOT NULL REFERENCES ap_actors(id) ON DELETE CASCADE,
+    uri             TEXT UNIQUE,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE(option_id, actor_id)
+);
+
+CREATE INDEX idx_poll_votes_voter ON ap_poll_votes (object_id, actor_id);
+
 -- =========================================================================
 -- ap_activities: The activity log — every federation event.
 -- =========================================================================
@@ -111,6 +235,7 @@ CREATE TABLE ap_activities (
 CREATE INDEX idx_activities_actor_id ON ap_activities (actor_id);
 CREATE INDEX idx_activities_object_uri ON ap_activities (object_uri) WHERE object_uri IS NOT NULL;
 CREATE INDEX idx_activities_type ON ap_activities (activity_type);
+CREATE INDEX idx_activities_actor_type ON ap_activities (actor_id, activity_type, id);
 CREATE INDEX idx_activities_local ON ap_activities (local) WHERE local = true;
 CREATE INDEX idx_activities_unprocessed ON ap_activities (id) WHERE processed = false;
 CREATE INDEX idx_activities_created_at ON ap_activities (created_at DESC);
@@ -160,6 +285,66 @@ CREATE TABLE ap_announces (
 
 CREATE INDEX idx_announces_object_id ON ap_announces (object_id);
 
+-- =========================================================================
+-- ap_pins: Posts pinned to an actor's featured collection. Remote pins can
+-- arrive before their object does (object_id NULL until it is stored).
+-- =========================================================================
+CREATE TABLE ap_pins (
+    id              BIGSERIAL PRIMARY KEY,
+    actor_id        BIGINT NOT NULL REFERENCES ap_actors(id) ON DELETE CASCADE,
+    object_uri      TEXT NOT NULL,
+    object_id       BIGINT REFERENCES ap_objects(id) ON DELETE CASCADE,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE(actor_id, object_uri)
+);
+
+-- =========================================================================
+-- ap_notifications: Interactions a local actor should be told about.
+-- =========================================================================
+CREATE TABLE ap_notifications (
+    id              BIGSERIAL PRIMARY KEY,
+    actor_id        BIGINT NOT NULL REFERENCES ap_actors(id) ON DELETE CASCADE,  -- local recipient
+    notification_type ApNotificationType NOT NULL,
+    from_actor_id   BIGINT NOT NULL REFERENCES ap_actors(id) ON DELETE CASCADE,
+    object_id       BIGINT REFERENCES ap_objects(id) ON DELETE CASCADE,          -- NULL for follows
+    read_at         TIMESTAMPTZ,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_notifications_actor ON ap_notifications (actor_id, id DESC);
+CREATE INDEX idx_notifications_unread ON ap_notifications (actor_id) WHERE read_at IS NULL;
+
+-- =========================================================================
+-- ap_conversation_reads: How far a local actor has read each direct
+-- conversation (keyed by conversation URI, or the post URI if it has none).
+-- =========================================================================
+CREATE TABLE ap_conversation_reads (
+    actor_id        BIGINT NOT NULL REFERENCES ap_actors(id) ON DELETE CASCADE,
+    conversation_uri TEXT NOT NULL,
+    last_read_id    BIGINT NOT NULL,
+    updated_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (actor_id, conversation_uri)
+);
+
+-- =========================================================================
+-- ap_lists: Named lists of followed actors, each with its own timeline.
+-- =========================================================================
+CREATE TABLE ap_lists (
+    id              BIGSERIAL PRIMARY KEY,
+    actor_id        BIGINT NOT NULL REFERENCES ap_actors(id) ON DELETE CASCADE,  -- local owner
+    title           TEXT NOT NULL,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_lists_actor ON ap_lists (actor_id);
+
+CREATE TABLE ap_list_members (
+    list_id         BIGINT NOT NULL REFERENCES ap_lists(id) ON DELETE CASCADE,
+    actor_id        BIGINT NOT NULL REFERENCES ap_actors(id) ON DELETE CASCADE,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (list_id, actor_id)
+);
+
 -- =========================================================================
 -- ap_blocks: Actor-level and domain-level blocks.
 -- =========================================================================
@@ -178,6 +363,21 @@ CREATE TABLE ap_blocks (
 
 CREATE INDEX idx_blocks_actor ON ap_blocks (actor_id) WHERE actor_id IS NOT NULL;
 CREATE INDEX idx_blocks_domain ON ap_blocks (blocked_domain) WHERE blocked_domain IS NOT NULL;
+CREATE UNIQUE INDEX idx_blocks_actor_target ON ap_blocks (actor_id, blocked_actor_id)
+    WHERE blocked_actor_id IS NOT NULL;
+
+-- =========================================================================
+-- ap_mutes: Actors a local actor has muted. Muted actors are hidden from
+-- timelines (and, optionally, notifications) without being told.
+-- =========================================================================
+CREATE TABLE ap_mutes (
+    actor_id        BIGINT NOT NULL REFERENCES ap_actors(id) ON DELETE CASCADE,
+    muted_actor_id  BIGINT NOT NULL REFERENCES ap_actors(id) ON DELETE CASCADE,
+    notifications   BOOLEAN NOT NULL DEFAULT true,     -- also mute their notifications
+    expires_at      TIMESTAMPTZ,                       -- NULL = indefinitely
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (actor_id, muted_actor_id)
+);
 
 -- =========================================================================
 -- ap_deliveries: Outbound federation delivery queue.
@@ -197,9 +397,20 @@ CREATE TABLE ap_deliveries (
 
 CREATE INDEX idx_deliveries_pending ON ap_deliveries (next_retry_at)
     WHERE status = 'Queued' OR status = 'Failed';
-CREATE INDEX idx_deliveries_activity ON ap_deliveries (activity_id);
+CREATE UNIQUE INDEX idx_deliveries_activity_inbox ON ap_deliveries (activity_id, inbox_uri);
 CREATE INDEX idx_deliveries_status ON ap_deliveries (status);
 
+-- =========================================================================
+-- ap_signature_schemes: The HTTP signature scheme each remote server is
+-- known to accept, learned by double-knocking (RFC 9421 first, then
+-- draft-cavage) or from the signatures it sends us.
+-- =========================================================================
+CREATE TABLE ap_signature_schemes (
+    domain          TEXT PRIMARY KEY,
+    scheme          ApSignatureScheme NOT NULL,
+    updated_at      TIMESTAMPTZ NOT NULL DEFAULT now()    -- when the scheme last changed
+);
+
 -- =========================================================================
 -- ap_actor_stats: Denormalized counters (Mastodon pattern — avoids write
 -- contention on the main actors table).
@@ -218,7 +429,10 @@ CREATE TABLE ap_actor_stats (
         ApActivityType,
         ApObjectType,
         ApVisibility,
-        ApDeliveryStatus
+        ApNotificationType,
+        ApKeyType,
+        ApDeliveryStatus,
+        ApSignatureScheme
     ]
 );
 
@@ -377,6 +591,26 @@ CREATE TRIGGER trg_notify_object
     FOR EACH ROW
     EXECUTE FUNCTION ap_notify_object();
 
+-- Notify a local actor of a new notification on their own channel,
+-- ap_notifications_<actor id>, so clients can stream them
+CREATE OR REPLACE FUNCTION ap_notify_notification()
+RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify('ap_notifications_' || NEW.actor_id, json_build_object(
+        'id', NEW.id,
+        'type', NEW.notification_type::text,
+        'from_actor_id', NEW.from_actor_id,
+        'object_id', NEW.object_id
+    )::text);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trg_notify_notification
+    AFTER INSERT ON ap_notifications
+    FOR EACH ROW
+    EXECUTE FUNCTION ap_notify_notification();
+
 -- Local actors only
 CREATE VIEW ap_local_actors AS
     SELECT a.*, s.statuses_count, s.followers_count, s.following_count, s.last_status_at
@@ -408,3 +642,51 @@ CREATE VIEW ap_local_timeline AS
     name = "schema_views",
     requires = ["schema_tables"]
 );
+
+// =============================================================================
+// Visibility
+// =============================================================================
+
+extension_sql!(
+    r#"
+-- Whether an object may be shown to a local viewer (NULL = anonymous).
+-- Public and Unlisted posts are visible to anyone; FollowersOnly posts to the
+-- author's accepted followers; every post to its author and the actors it
+-- addresses or mentions. Posts from blocked actors and domains are hidden,
+-- as are posts from actors who have blocked the viewer.
+CREATE OR REPLACE FUNCTION ap_can_view(p_object_id BIGINT, p_viewer_id BIGINT)
+RETURNS BOOLEAN AS $$
+    SELECT EXISTS (
+        SELECT 1
+        FROM ap_objects o
+        JOIN ap_actors a ON a.id = o.actor_id
+        WHERE o.id = p_object_id
+          AND o.deleted_at IS NULL
+          AND (
+              o.visibility IN ('Public', 'Unlisted')
+              OR o.actor_id = p_viewer_id
+              OR (o.visibility = 'FollowersOnly' AND EXISTS (
+                  SELECT 1 FROM ap_follows f
+                  WHERE f.follower_id = p_viewer_id
+                    AND f.following_id = o.actor_id
+                    AND f.accepted))
+              OR EXISTS (
+                  SELECT 1 FROM ap_object_recipients r
+                  WHERE r.object_id = o.id AND r.actor_id = p_viewer_id)
+              OR EXISTS (
+                  SELECT 1 FROM ap_mentions m
+                  WHERE m.object_id = o.id AND m.actor_id = p_viewer_id)
+          )
+          AND NOT EXISTS (
+              SELECT 1 FROM ap_blocks b
+              WHERE (b.blocked_domain = a.domain
+                     AND (b.actor_id IS NULL OR b.actor_id = p_viewer_id))
+                 OR (b.actor_id = p_viewer_id AND b.blocked_actor_id = a.id)
+                 OR (b.actor_id = a.id AND b.blocked_actor_id = p_viewer_id)
+          )
+    )
+$$ LANGUAGE sql STABLE;
+"#,
+    name = "schema_visibility",
+    requires = ["schema_tables"]
+);
@@ -1,16 +1,42 @@
 use pgrx::prelude::*;
 use serde_json::json;
 
+use crate::actors::local_actor;
 use crate::guc::base_url;
-use crate::util::json_str;
+use crate::media::serialize_attachments;
+use crate::polls::serialize_poll;
+use crate::tags::serialize_tags;
+use crate::util::{json_str, AS_PUBLIC};
 
 const PAGE_SIZE: i64 = 20;
 
-/// Serialize a Note/Article object to ActivityStreams JSON-LD.
+/// Serialize a Note/Article object to ActivityStreams JSON-LD, as seen by a
+/// local viewer; viewer NULL sees what an anonymous visitor would. Returns
+/// NULL if the object is unknown, deleted, or hidden from the viewer.
 #[pg_extern]
-fn ap_serialize_object(object_uri: &str) -> pgrx::Json {
+fn ap_serialize_object(
+    object_uri: &str,
+    viewer: default!(Option<&str>, "NULL"),
+) -> Option<pgrx::Json> {
+    let viewer_id = viewer.map(|username| local_actor(username).0);
+
+    let visible = Spi::get_one_with_args::<bool>(
+        "SELECT ap_can_view(id, $2) FROM ap_objects WHERE uri = $1",
+        &[object_uri.into(), viewer_id.into()],
+    )
+    .ok()
+    .flatten()
+    .unwrap_or(false);
+
+    visible.then(|| serialize_object(object_uri))
+}
+
+/// Serialize an object regardless of who may see it, e.g. to federate it to
+/// its own audience.
+pub fn serialize_object(object_uri: &str) -> pgrx::Json {
     let row = Spi::get_one_with_args::<pgrx::Json>(
         "SELECT json_build_object(
+            'id', o.id,
             'uri', o.uri,
             'object_type', o.object_type::text,
             'content', o.content,
@@ -22,8 +48,11 @@ fn ap_serialize_object(object_uri: &str) -> pgrx::Json {
             'published_at', o.published_at,
             'edited_at', o.edited_at,
             'language', o.language,
+            'to_uris', o.to_uris,
+            'cc_uris', o.cc_uris,
             'actor_uri', a.uri,
             'actor_username', a.username,
+            'local', a.domain IS NULL,
             'followers_uri', a.followers_uri
         )::json FROM ap_objects o
         JOIN ap_actors a ON a.id = o.actor_id
@@ -40,15 +69,27 @@ fn ap_serialize_object(object_uri: &str) -> pgrx::Json {
     let actor_uri = r["actor_uri"].as_str().unwrap();
     let followers_uri = r["followers_uri"].as_str();
 
-    let public = "https://www.w3.org/ns/activitystreams#Public";
+    // Objects stored before addressing was recorded default to Public
+    let to = if r["to_uris"].is_array() {
+        r["to_uris"].clone()
+    } else {
+        json!([AS_PUBLIC])
+    };
+    let cc = if r["cc_uris"].is_array() {
+        r["cc_uris"].clone()
+    } else if r["to_uris"].is_array() {
+        json!([])
+    } else {
+        followers_uri.map(|f| json!([f])).unwrap_or(json!([]))
+    };
 
     let mut doc = json!({
         "@context": "https://www.w3.org/ns/activitystreams",
         "id": uri,
         "type": obj_type,
         "attributedTo": actor_uri,
-        "to": [public],
-        "cc": followers_uri.map(|f| json!([f])).unwrap_or(json!([])),
+        "to": to,
+        "cc": cc,
         "published": r["published_at"],
     });
 
@@ -73,15 +114,317 @@ fn ap_serialize_object(object_uri: &str) -> pgrx::Json {
     }
     if let Some(conv) = r["conversation_uri"].as_str() {
         obj.insert("conversation".into(), json!(conv));
+        obj.insert("context".into(), json!(conv));
     }
     if !r["edited_at"].is_null() {
         obj.insert("updated".into(), r["edited_at"].clone());
     }
 
+    let object_id = r["id"].as_i64().unwrap();
+    let tags = serialize_tags(object_id);
+    if !tags.is_empty() {
+        obj.insert("tag".into(), json!(tags));
+    }
+    let attachments = serialize_attachments(object_id);
+    if !attachments.is_empty() {
+        obj.insert("attachment".into(), json!(attachments));
+    }
+    if obj_type == "Question" {
+        if let Some(poll) = serialize_poll(object_id) {
+            obj.extend(poll);
+        }
+    }
+
+    // Interaction collections are only ours to serve for local objects
+    if r["local"].as_bool().unwrap_or(false) {
+        for kind in [
+            ObjectCollection::Replies,
+            ObjectCollection::Likes,
+            ObjectCollection::Shares,
+        ] {
+            let mut summary = serialize_object_collection(uri, kind, None);
+            summary.as_object_mut().unwrap().remove("@context");
+            obj.insert(kind.name().into(), summary);
+        }
+    }
+
     pgrx::Json(doc)
 }
 
+// =============================================================================
+// Object interaction collections
+// =============================================================================
+
+/// Serialize the public replies to an object as an OrderedCollection of reply
+/// URIs, oldest first. If page is NULL, returns the collection summary.
+#[pg_extern]
+fn ap_serialize_replies(object_uri: &str, page: Option<i32>) -> pgrx::Json {
+    ensure_public_object(object_uri);
+    pgrx::Json(serialize_object_collection(
+        object_uri,
+        ObjectCollection::Replies,
+        page,
+    ))
+}
+
+/// Serialize the Likes of an object as an OrderedCollection of Like URIs.
+/// If page is NULL, returns the collection summary.
+#[pg_extern]
+fn ap_serialize_likes(object_uri: &str, page: Option<i32>) -> pgrx::Json {
+    ensure_public_object(object_uri);
+    pgrx::Json(serialize_object_collection(
+        object_uri,
+        ObjectCollection::Likes,
+        page,
+    ))
+}
+
+/// Serialize the Announces (boosts) of an object as an OrderedCollection of
+/// Announce URIs. If page is NULL, returns the collection summary.
+#[pg_extern]
+fn ap_serialize_shares(object_uri: &str, page: Option<i32>) -> pgrx::Json {
+    ensure_public_object(object_uri);
+    pgrx::Json(serialize_object_collection(
+        object_uri,
+        ObjectCollection::Shares,
+        page,
+    ))
+}
+
+// =============================================================================
+// Conversation contexts
+// =============================================================================
+
+/// Serialize a conversation (FEP-7888 `context`) as an OrderedCollection of
+/// the URIs of its public posts, oldest first. If page is NULL, returns the
+/// collection summary.
+#[pg_extern]
+fn ap_serialize_context(context_uri: &str, page: Option<i32>) -> pgrx::Json {
+    let exists = Spi::get_one_with_args::<bool>(
+        "SELECT EXISTS(SELECT 1 FROM ap_objects WHERE conversation_uri = $1)",
+        &[context_uri.into()],
+    )
+    .unwrap()
+    .unwrap_or(false);
+
+    if !exists {
+        pgrx::error!("context '{}' not found", context_uri);
+    }
+
+    let mut doc = serialize_uri_collection(
+        context_uri,
+        context_uri,
+        "SELECT count(*) FROM ap_objects
+         WHERE conversation_uri = $1 AND deleted_at IS NULL
+         AND visibility IN ('Public', 'Unlisted')",
+        "SELECT uri FROM ap_objects
+         WHERE conversation_uri = $1 AND deleted_at IS NULL
+         AND visibility IN ('Public', 'Unlisted')
+         ORDER BY published_at, id
+         LIMIT $2 OFFSET $3",
+        page,
+    );
+
+    // FEP-7888: the conversation is owned by the author of its first post
+    if page.is_none() {
+        let owner = Spi::get_one_with_args::<String>(
+            "SELECT a.uri FROM ap_objects o
+             JOIN ap_actors a ON a.id = o.actor_id
+             WHERE o.conversation_uri = $1 AND o.in_reply_to_uri IS NULL
+             ORDER BY o.published_at, o.id LIMIT 1",
+            &[context_uri.into()],
+        )
+        .ok()
+        .flatten();
+        if let Some(owner) = owner {
+            doc.as_object_mut()
+                .unwrap()
+                .insert("attributedTo".into(), json!(owner));
+        }
+    }
+
+    pgrx::Json(doc)
+}
+
+/// The per-object collections, served at `{object_uri}/{name}`.
+#[derive(Clone, Copy)]
+enum ObjectCollection {
+    Replies,
+    Likes,
+    Shares,
+}
+
+impl ObjectCollection {
+    fn name(self) -> &'static str {
+        match self {
+            ObjectCollection::Replies => "replies",
+            ObjectCollection::Likes => "likes",
+            ObjectCollection::Shares => "shares",
+        }
+    }
+
+    /// Query returning the item URIs of object `$1`'s collection, paged by
+    /// LIMIT $2 OFFSET $3.
+    fn items_sql(self) -> &'static str {
+        match self {
+            ObjectCollection::Replies => {
+                "SELECT r.uri FROM ap_objects r
+                 WHERE r.in_reply_to_uri = $1 AND r.deleted_at IS NULL
+                 AND r.visibility IN ('Public', 'Unlisted')
+                 ORDER BY r.published_at, r.id
+                 LIMIT $2 OFFSET $3"
+            }
+            ObjectCollection::Likes => {
+                "SELECT l.uri FROM ap_likes l
+                 JOIN ap_objects o ON o.id = l.object_id
+                 WHERE o.uri = $1 AND l.uri IS NOT NULL
+                 ORDER BY l.id
+                 LIMIT $2 OFFSET $3"
+            }
+            ObjectCollection::Shares => {
+                "SELECT s.uri FROM ap_announces s
+                 JOIN ap_objects o ON o.id = s.object_id
+                 WHERE o.uri = $1 AND s.uri IS NOT NULL
+                 ORDER BY s.id
+                 LIMIT $2 OFFSET $3"
+            }
+        }
+    }
+
+    /// Query counting every item of object `$1`'s collection.
+    fn count_sql(self) -> &'static str {
+        match self {
+            ObjectCollection::Replies => {
+                "SELECT count(*) FROM ap_objects r
+                 WHERE r.in_reply_to_uri = $1 AND r.deleted_at IS NULL
+                 AND r.visibility IN ('Public', 'Unlisted')"
+            }
+            ObjectCollection::Likes => {
+                "SELECT count(*) FROM ap_likes l
+                 JOIN ap_objects o ON o.id = l.object_id
+                 WHERE o.uri = $1 AND l.uri IS NOT NULL"
+            }
+            ObjectCollection::Shares => {
+                "SELECT count(*) FROM ap_announces s
+                 JOIN ap_objects o ON o.id = s.object_id
+                 WHERE o.uri = $1 AND s.uri IS NOT NULL"
+            }
+        }
+    }
+}
+
+/// Serialize one of an object's collections as an OrderedCollection summary
+/// (page NULL) or an OrderedCollectionPage.
+fn serialize_object_collection(
+    object_uri: &str,
+    kind: ObjectCollection,
+    page: Option<i32>,
+) -> serde_json::Value {
+    serialize_uri_collection(
+        &format!("{}/{}", object_uri, kind.name()),
+        object_uri,
+        kind.count_sql(),
+        kind.items_sql(),
+        page,
+    )
+}
+
+/// Serialize a collection of URIs keyed by `key`. `count_sql` counts every item
+/// of `$1`; `items_sql` returns item URIs of `$1` paged by LIMIT $2 OFFSET $3.
+fn serialize_uri_collection(
+    collection_uri: &str,
+    key: &str,
+    count_sql: &str,
+    items_sql: &str,
+    page: Option<i32>,
+) -> serde_json::Value {
+    match page {
+        None => {
+            let total = Spi::get_one_with_args::<i64>(count_sql, &[key.into()])
+                .unwrap()
+                .unwrap_or(0);
+
+            json!({
+                "@context": "https://www.w3.org/ns/activitystreams",
+                "id": collection_uri,
+                "type": "OrderedCollection",
+                "totalItems": total,
+                "first": format!("{}?page=1", collection_uri),
+            })
+        }
+        Some(p) => {
+            let offset = ((p.max(1) - 1) as i64) * PAGE_SIZE;
+
+            let items: Vec<String> = Spi::connect(|client| {
+                let mut results = Vec::new();
+                let tup_table = client
+                    .select(
+                        items_sql,
+                        None,
+                        &[key.into(), PAGE_SIZE.into(), offset.into()],
+                    )
+                    .expect("failed to query collection");
+
+                for row in tup_table {
+                    if let Some(uri) = row
+                        .get_datum_by_ordinal(1)
+                        .unwrap()
+                        .value::<String>()
+                        .ok()
+                        .flatten()
+                    {
+                        results.push(uri);
+                    }
+                }
+
+                results
+            });
+
+            let mut page_doc = json!({
+                "@context": "https://www.w3.org/ns/activitystreams",
+                "id": format!("{}?page={}", collection_uri, p),
+                "type": "OrderedCollectionPage",
+                "partOf": collection_uri,
+                "orderedItems": items,
+            });
+
+            if p > 1 {
+                page_doc.as_object_mut().unwrap().insert(
+                    "prev".into(),
+                    json!(format!("{}?page={}", collection_uri, p - 1)),
+                );
+            }
+
+            if items.len() as i64 >= PAGE_SIZE {
+                page_doc.as_object_mut().unwrap().insert(
+                    "next".into(),
+                    json!(format!("{}?page={}", collection_uri, p + 1)),
+                );
+            }
+
+            page_doc
+        }
+    }
+}
+
+/// Error unless the object exists and is Public or Unlisted; the collections
+/// of followers-only posts and DMs are not served, as in the outbox.
+fn ensure_public_object(object_uri: &str) {
+    let exists = Spi::get_one_with_args::<bool>(
+        "SELECT EXISTS(SELECT 1 FROM ap_objects WHERE uri = $1 AND deleted_at IS NULL
+         AND visibility IN ('Public', 'Unlisted'))",
+        &[object_uri.into()],
+    )
+    .unwrap()
+    .unwrap_or(false);
+
+    if !exists {
+        pgrx::error!("object '{}' not found", object_uri);
+    }
+}
+
 /// Serialize an actor's outbox as an OrderedCollection or OrderedCollectionPage.
+/// Only Creates of Public and Unlisted posts are listed (not DMs or poll votes).
 /// If page is NULL, returns the collection summary. Otherwise returns the page.
 #[pg_extern]
 fn ap_serialize_outbox(username: &str, page: Option<i32>) -> pgrx::Json {
@@ -94,8 +437,10 @@ fn ap_serialize_outbox(username: &str, page: Option<i32>) -> pgrx::Json {
             let total = Spi::get_one_with_args::<i64>(
                 "SELECT count(*) FROM ap_activities act
                  JOIN ap_actors a ON a.id = act.actor_id
+                 JOIN ap_objects o ON o.uri = act.object_uri
                  WHERE a.username = $1 AND a.domain IS NULL
-                 AND act.local = true AND act.activity_type = 'Create'",
+                 AND act.local = true AND act.activity_type = 'Create'
+                 AND o.visibility IN ('Public', 'Unlisted')",
                 &[username.into()],
             )
             .unwrap()
@@ -120,8 +465,10 @@ fn ap_serialize_outbox(username: &str, page: Option<i32>) -> pgrx::Json {
                         "SELECT ap_serialize_activity(act.uri)::json
                          FROM ap_activities act
                          JOIN ap_actors a ON a.id = act.actor_id
+                         JOIN ap_objects o ON o.uri = act.object_uri
                          WHERE a.username = $1 AND a.domain IS NULL
                          AND act.local = true AND act.activity_type = 'Create'
+                         AND o.visibility IN ('Public', 'Unlisted')
                          ORDER BY act.created_at DESC
                          LIMIT $2 OFFSET $3",
                         None,
@@ -422,19 +769,54 @@ fn ap_serialize_activity(activity_uri: &str) -> pgrx::Json {
     pgrx::Json(doc)
 }
 
-/// Serialize an actor's featured/pinned posts as an OrderedCollection.
-/// Returns an empty collection (pinning can be added via a `pinned` column later).
-/// This endpoint must exist for Mastodon compatibility.
+/// Serialize an actor's featured (pinned) posts as an OrderedCollection,
+/// most recently pinned first. Mastodon fetches this to show pinned posts.
 #[pg_extern]
 fn ap_serialize_featured(username: &str) -> pgrx::Json {
     let base = base_url();
     let collection_uri = format!("{}/users/{}/collections/featured", base, username);
 
+    let items: Vec<serde_json::Value> = Spi::connect(|client| {
+        let mut results = Vec::new();
+        let tup_table = client
+            .select(
+                "SELECT ap_serialize_object(o.uri)::json
+                 FROM ap_pins p
+                 JOIN ap_actors a ON a.id = p.actor_id
+                 JOIN ap_objects o ON o.id = p.object_id
+                 WHERE a.username = $1 AND a.domain IS NULL
+                 AND o.deleted_at IS NULL
+                 AND o.visibility IN ('Public', 'Unlisted')
+                 ORDER BY p.id DESC",
+                None,
+                &[username.into()],
+            )
+            .expect("failed to query featured posts");
+
+        for row in tup_table {
+            if let Some(j) = row
+                .get_datum_by_ordinal(1)
+                .unwrap()
+                .value::<pgrx::Json>()
+                .ok()
+                .flatten()
+            {
+                let mut item = j.0;
+                if let Some(obj) = item.as_object_mut() {
+                    obj.remove("@context");
+                }
+                results.push(item);
+            }
+        }
+
+        results
+    });
+
     pgrx::Json(json!({
         "@context": "https://www.w3.org/ns/activitystreams",
         "id": collection_uri,
         "type": "OrderedCollection",
-        "totalItems": 0,
-        "orderedItems": [],
+        "totalItems": items.len(),
+        "orderedItems": items,
     }))
 }
@@ -0,0 +1,237 @@
+use pgrx::prelude::*;
+use serde_json::Value;
+
+use crate::guc::{base_url, get_domain};
+use crate::util::{json_str, parse_mentions};
+
+// =============================================================================
+// Mentions
+// =============================================================================
+
+/// Resolve the `@user` / `@user@domain` mentions in a local post's text to
+/// known actors. Returns (actor URI, `@user@domain` name) pairs; mentions of
+/// actors we have never seen are skipped.
+pub fn resolve_mentions(text: &str) -> Vec<(String, Option<String>)> {
+    let local_domain = get_domain();
+    let mut resolved: Vec<(String, Option<String>)> = Vec::new();
+
+    for (username, domain) in parse_mentions(text) {
+        // Mentions of our own domain refer to local actors
+        let domain = domain.filter(|d| *d != local_domain.to_lowercase());
+
+        let actor = Spi::get_two_with_args::<String, String>(
+            "SELECT uri, '@' || username || '@' || coalesce(domain, $3)
+             FROM ap_actors
+             WHERE lower(username) = lower($1) AND lower(domain) IS NOT DISTINCT FROM $2",
+            &[username.into(), domain.into(), local_domain.clone().into()],
+        );
+
+        if let Ok((Some(uri), Some(name))) = actor {
+            if !resolved.iter().any(|(u, _)| *u == uri) {
+                resolved.push((uri, Some(name)));
+            }
+        }
+    }
+
+    resolved
+}
+
+/// Record the actors an object mentions as (actor URI, name) pairs, linking
+/// those we already know.
+pub fn record_mentions(object_id: i64, mentions: &[(String, Option<String>)]) {
+    if mentions.is_empty() {
+        return;
+    }
+
+    let (uris, names): (Vec<String>, Vec<Option<String>>) = mentions.iter().cloned().unzip();
+
+    Spi::run_with_args(
+        "INSERT INTO ap_mentions (object_id, actor_uri, name, actor_id)
+         SELECT $1, m.uri, m.name, a.id
+         FROM unnest($2::text[], $3::text[]) AS m(uri, name)
+         LEFT JOIN ap_actors a ON a.uri = m.uri
+         ON CONFLICT (object_id, actor_uri) DO NOTHING",
+        &[object_id.into(), uris.into(), names.into()],
+    )
+    .expect("failed to record mentions");
+}
+
+/// Replace the mentions recorded for an object, e.g. after an edit.
+pub fn sync_mentions(object_id: i64, mentions: &[(String, Option<String>)]) {
+    Spi::run_with_args(
+        "DELETE FROM ap_mentions WHERE object_id = $1",
+        &[object_id.into()],
+    )
+    .expect("failed to clear mentions");
+
+    record_mentions(object_id, mentions);
+}
+
+// =============================================================================
+// Hashtags
+// =============================================================================
+
+/// Replace the hashtags linked to an object. Names must already be normalized
+/// (lowercase, without the leading '#').
+pub fn sync_hashtags(object_id: i64, names: &[String]) {
+    Spi::run_with_args(
+        "DELETE FROM ap_object_hashtags WHERE object_id = $1",
+        &[object_id.into()],
+    )
+    .expect("failed to clear hashtags");
+
+    if names.is_empty() {
+        return;
+    }
+
+    Spi::run_with_args(
+        "INSERT INTO ap_hashtags (name) SELECT unnest($1::text[])
+         ON CONFLICT (name) DO NOTHING",
+        &[names.to_vec().into()],
+    )
+    .expect("failed to insert hashtags");
+
+    Spi::run_with_args(
+        "INSERT INTO ap_object_hashtags (object_id, hashtag_id)
+         SELECT $1, id FROM ap_hashtags WHERE name = ANY($2)
+         ON CONFLICT DO NOTHING",
+        &[object_id.into(), names.to_vec().into()],
+    )
+    .expect("failed to link hashtags");
+}
+
+// =============================================================================
+// Inbound tags
+// =============================================================================
+
+/// Replace a remote object's mentions, hashtags and custom emoji with those in
+/// its `tag` array. Other tag types are ignored.
+pub fn sync_object_tags(object_id: i64, tag: Option<&Value>) {
+    let tags: Vec<&Value> = match tag {
+        Some(Value::Array(items)) => items.iter().collect(),
+        Some(item @ Value::Object(_)) => vec![item],
+        _ => Vec::new(),
+    };
+
+    let mut mentions: Vec<(String, Option<String>)> = Vec::new();
+    let mut hashtags: Vec<String> = Vec::new();
+
+    Spi::run_with_args(
+        "DELETE FROM ap_mentions WHERE object_id = $1",
+        &[object_id.into()],
+    )
+    .expect("failed to clear mentions");
+    Spi::run_with_args(
+        "DELETE FROM ap_object_emojis WHERE object_id = $1",
+        &[object_id.into()],
+    )
+    .expect("failed to clear emojis");
+
+    for tag in tags {
+        match json_str(tag, "type").as_deref() {
+            Some("Mention") => {
+                if let Some(href) = json_str(tag, "href") {
+                    if !mentions.iter().any(|(uri, _)| *uri == href) {
+                        mentions.push((href, json_str(tag, "name")));
+                    }
+                }
+            }
+            Some("Hashtag") => {
+                let name = json_str(tag, "name")
+                    .map(|n| n.trim_start_matches('#').to_lowercase())
+                    .unwrap_or_default();
+                if !name.is_empty() && !hashtags.contains(&name) {
+                    hashtags.push(name);
+                }
+            }
+            Some("Emoji") => record_emoji(object_id, tag),
+            _ => {}
+        }
+    }
+
+    record_mentions(object_id, &mentions);
+    sync_hashtags(object_id, &hashtags);
+}
+
+/// Upsert a custom emoji from an `Emoji` tag and link it to the object. Emoji
+/// are keyed by shortcode and the author's domain, so one server cannot
+/// replace another's emoji.
+fn record_emoji(object_id: i64, tag: &Value) {
+    let shortcode = json_str(tag, "name")
+        .map(|n| n.trim_matches(':').to_string())
+        .unwrap_or_default();
+    let icon = tag.get("icon");
+    let image_url = match icon.and_then(|i| json_str(i, "url")) {
+        Some(url) if !shortcode.is_empty() => url,
+        _ => return,
+    };
+
+    let emoji_id = Spi::get_one_with_args::<i64>(
+        "INSERT INTO ap_emojis (shortcode, domain, uri, image_url, media_type)
+         SELECT $2, a.domain, $3, $4, $5
+         FROM ap_objects o
+         JOIN ap_actors a ON a.id = o.actor_id
+         WHERE o.id = $1 AND a.domain IS NOT NULL
+         ON CONFLICT (shortcode, domain) DO UPDATE SET
+            uri = EXCLUDED.uri,
+            image_url = EXCLUDED.image_url,
+            media_type = EXCLUDED.media_type,
+            updated_at = now()
+         RETURNING id",
+        &[
+            object_id.into(),
+            shortcode.into(),
+            json_str(tag, "id").into(),
+            image_url.into(),
+            icon.and_then(|i| json_str(i, "mediaType")).into(),
+        ],
+    )
+    .ok()
+    .flatten();
+
+    if let Some(emoji_id) = emoji_id {
+        Spi::run_with_args(
+            "INSERT INTO ap_object_emojis (object_id, emoji_id) VALUES ($1, $2)
+             ON CONFLICT DO NOTHING",
+            &[object_id.into(), emoji_id.into()],
+        )
+        .expect("failed to link emoji");
+    }
+}
+
+// =============================================================================
+// Serialization
+// =============================================================================
+
+/// Build an object's ActivityStreams `tag` array from the tag tables.
+pub fn serialize_tags(object_id: i64) -> Vec<Value> {
+    let tags = Spi::get_one_with_args::<pgrx::Json>(
+        "SELECT coalesce(json_agg(json_strip_nulls(t.tag)), '[]'::json)
+         FROM (
+            SELECT json_build_object('type', 'Mention', 'href', actor_uri, 'name', name) AS tag
+            FROM ap_mentions WHERE object_id = $1
+            UNION ALL
+            SELECT json_build_object('type', 'Hashtag',
+                'href', $2 || '/tags/' || h.name, 'name', '#' || h.name)
+            FROM ap_object_hashtags oh
+            JOIN ap_hashtags h ON h.id = oh.hashtag_id
+            WHERE oh.object_id = $1
+            UNION ALL
+            SELECT json_build_object('id', e.uri, 'type', 'Emoji',
+                'name', ':' || e.shortcode || ':',
+                'icon', json_build_object('type', 'Image',
+                    'mediaType', e.media_type, 'url', e.image_url))
+            FROM ap_object_emojis oe
+            JOIN ap_emojis e ON e.id = oe.emoji_id
+            WHERE oe.object_id = $1
+         ) t",
+        &[object_id.into(), base_url().into()],
+    )
+    .expect("failed to query tags")
+    .expect("tags query returned no rows");
+
+    match tags.0 {
+        Value::Array(tags) => tags,
+        _ => Vec::new(),
+    }
+}
@@ -53,6 +53,24 @@ pub enum ApVisibility {
     Direct,
 }
 
+/// Why a local actor is being notified.
+#[derive(PostgresEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApNotificationType {
+    Follow,
+    FollowRequest,
+    Like,
+    Announce,
+    Mention,
+    Reply,
+}
+
+/// Actor key algorithms.
+#[derive(PostgresEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApKeyType {
+    Rsa,
+    Ed25519,
+}
+
 /// Outbound delivery queue status.
 #[derive(PostgresEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ApDeliveryStatus {
@@ -61,3 +79,10 @@ pub enum ApDeliveryStatus {
     Failed,
     Expired,
 }
+
+/// HTTP signature schemes for outbound requests.
+#[derive(PostgresEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApSignatureScheme {
+    Rfc9421,
+    Cavage,
+}
@@ -1,5 +1,11 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use serde_json::Value;
 
+/// The ActivityStreams special collection addressing everyone.
+pub const AS_PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
 /// Extract a string field from a JSON object.
 pub fn json_str(obj: &Value, key: &str) -> Option<String> {
     obj.get(key).and_then(|v| v.as_str()).map(|s| s.to_string())
@@ -14,6 +20,18 @@ pub fn json_str_nested(obj: &Value, keys: &[&str]) -> Option<String> {
     current.as_str().map(|s| s.to_string())
 }
 
+/// Extract a reference that may be either a bare URI string or an embedded
+/// object with an "id" (e.g. the `object` of an activity).
+pub fn json_id(obj: &Value, key: &str) -> Option<String> {
+    obj.get(key).and_then(|v| {
+        if v.is_string() {
+            v.as_str().map(|s| s.to_string())
+        } else {
+            json_str(v, "id")
+        }
+    })
+}
+
 /// Simple domain extraction from a URI without pulling in the `url` crate.
 pub fn parse_domain(uri: &str) -> Option<String> {
     let after_scheme = uri
@@ -23,3 +41,206 @@ pub fn parse_domain(uri: &str) -> Option<String> {
     let domain = host.split(':').next()?;
     Some(domain.to_string())
 }
+
+/// Lowercase header names so lookups are case-insensitive, as HTTP requires.
+pub fn normalize_headers(headers: &Value) -> HashMap<String, String> {
+    headers
+        .as_object()
+        .map(|m| {
+            m.iter()
+                .filter_map(|(k, v)| v.as_str().map(|v| (k.to_lowercase(), v.to_string())))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Seconds since the Unix epoch, according to the server clock.
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Parse an HTTP date in IMF-fixdate form (e.g. `Sun, 09 Feb 2025 12:00:00 GMT`)
+/// into seconds since the Unix epoch.
+pub fn parse_http_date(date: &str) -> Option<i64> {
+    let (_weekday, rest) = date.trim().split_once(", ")?;
+    let parts: Vec<&str> = rest.split_whitespace().collect();
+    if parts.len() != 5 || parts[4] != "GMT" {
+        return None;
+    }
+
+    let day: i64 = parts[0].parse().ok()?;
+    let month = match parts[1] {
+        "Jan" => 1,
+        "Feb" => 2,
+        "Mar" => 3,
+        "Apr" => 4,
+        "May" => 5,
+        "Jun" => 6,
+        "Jul" => 7,
+        "Aug" => 8,
+        "Sep" => 9,
+        "Oct" => 10,
+        "Nov" => 11,
+        "Dec" => 12,
+        _ => return None,
+    };
+    let year: i64 = parts[2].parse().ok()?;
+
+    let mut hms = parts[3].split(':').map(|p| p.parse::<i64>().ok());
+    let (hour, minute, second) = (hms.next()??, hms.next()??, hms.next()??);
+
+    // Days from civil (Howard Hinnant's algorithm)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Format seconds since the Unix epoch as an ISO 8601 UTC timestamp
+/// (e.g. `2025-02-09T12:00:00Z`).
+pub fn format_iso8601(secs: i64) -> String {
+    let (year, month, day, time) = civil_from_unix(secs);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Format seconds since the Unix epoch as an HTTP date in IMF-fixdate form
+/// (e.g. `Sun, 09 Feb 2025 12:00:00 GMT`).
+pub fn format_http_date(secs: i64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let (year, month, day, time) = civil_from_unix(secs);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[secs.div_euclid(86400).rem_euclid(7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Split seconds since the Unix epoch into (year, month, day, seconds into
+/// the day), in UTC.
+fn civil_from_unix(secs: i64) -> (i64, i64, i64, i64) {
+    let days = secs.div_euclid(86400);
+
+    // Civil from days (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day, secs.rem_euclid(86400))
+}
+
+/// Find `@user` and `@user@domain` mentions in plain text, in order of
+/// appearance and without duplicates. Returns (username, domain) pairs.
+pub fn parse_mentions(text: &str) -> Vec<(String, Option<String>)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut found = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        // Skip email addresses and URL paths like /@user
+        if chars[i] != '@'
+            || (i > 0 && (chars[i - 1].is_alphanumeric() || "_/@".contains(chars[i - 1])))
+        {
+            i += 1;
+            continue;
+        }
+
+        let (username, mut end) = take_name(&chars, i + 1, |c| {
+            c.is_ascii_alphanumeric() || "_.-".contains(c)
+        });
+        if username.is_empty() {
+            i += 1;
+            continue;
+        }
+
+        let mut domain = None;
+        if end < chars.len() && chars[end] == '@' {
+            let (host, host_end) =
+                take_name(&chars, end + 1, |c| c.is_alphanumeric() || ".-".contains(c));
+            if !host.is_empty() {
+                domain = Some(host.to_lowercase());
+                end = host_end;
+            }
+        }
+
+        let mention = (username, domain);
+        if !found.contains(&mention) {
+            found.push(mention);
+        }
+        i = end;
+    }
+
+    found
+}
+
+/// Find `#tag` hashtags in plain text, lowercased, in order of appearance and
+/// without duplicates. All-digit tags (e.g. `#1`) are ignored.
+pub fn parse_hashtags(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut found = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        // Skip HTML entities (&#39;) and URL fragments
+        if chars[i] != '#'
+            || (i > 0 && (chars[i - 1].is_alphanumeric() || "_&/".contains(chars[i - 1])))
+        {
+            i += 1;
+            continue;
+        }
+
+        let (tag, end) = take_name(&chars, i + 1, |c| c.is_alphanumeric() || c == '_');
+        if tag.chars().any(|c| !c.is_ascii_digit()) {
+            let tag = tag.to_lowercase();
+            if !found.contains(&tag) {
+                found.push(tag);
+            }
+        }
+        i = end.max(i + 1);
+    }
+
+    found
+}
+
+/// Take the run of `allowed` characters starting at `start`, dropping trailing
+/// `.` and `-` (sentence punctuation). Returns the run and the index after it.
+fn take_name(chars: &[char], start: usize, allowed: impl Fn(char) -> bool) -> (String, usize) {
+    let mut end = start;
+    while end < chars.len() && allowed(chars[end]) {
+        end += 1;
+    }
+    while end > start && matches!(chars[end - 1], '.' | '-') {
+        end -= 1;
+    }
+    (chars[start..end].iter().collect(), end)
+}
@@ -339,7 +339,7 @@ BEGIN
     INTO priv_pem, pub_pem, key_id
     FROM ap_keys k
     JOIN ap_actors a ON k.actor_id = a.id
-    WHERE a.username = 'endpoint_alice' AND a.domain IS NULL;
+    WHERE a.username = 'endpoint_alice' AND a.domain IS NULL AND k.key_type = 'Rsa';
 
     test_digest := ap_digest(test_body);
 